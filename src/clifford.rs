@@ -0,0 +1,305 @@
+use hugr::{
+    builder::{BuildError, DFGBuilder, Dataflow, DataflowHugr},
+    extension::{prelude::QB_T, ExtensionRegistry},
+    types::{FunctionType, Type},
+    Hugr, Wire,
+};
+use tket2::Tk2Op;
+
+/// A Clifford gate available as a `Tk2Op`, i.e. a gate we know how to
+/// conjugate Paulis by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CliffordGate {
+    H,
+    S,
+    Sdg,
+    X,
+    Y,
+    Z,
+    CX,
+    CZ,
+}
+
+impl CliffordGate {
+    /// Every supported Clifford gate.
+    pub const ALL: [CliffordGate; 8] = [
+        CliffordGate::H,
+        CliffordGate::S,
+        CliffordGate::Sdg,
+        CliffordGate::X,
+        CliffordGate::Y,
+        CliffordGate::Z,
+        CliffordGate::CX,
+        CliffordGate::CZ,
+    ];
+
+    /// The number of qubits the gate acts on.
+    pub fn n_qubits(self) -> usize {
+        match self {
+            CliffordGate::CX | CliffordGate::CZ => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl From<CliffordGate> for Tk2Op {
+    fn from(gate: CliffordGate) -> Self {
+        match gate {
+            CliffordGate::H => Tk2Op::H,
+            CliffordGate::S => Tk2Op::S,
+            CliffordGate::Sdg => Tk2Op::Sdg,
+            CliffordGate::X => Tk2Op::X,
+            CliffordGate::Y => Tk2Op::Y,
+            CliffordGate::Z => Tk2Op::Z,
+            CliffordGate::CX => Tk2Op::CX,
+            CliffordGate::CZ => Tk2Op::CZ,
+        }
+    }
+}
+
+/// A Pauli operator on `n` qubits, up to a global phase.
+///
+/// The operator is X^x Z^z, where the i-th entry of `x` (resp. `z`) is the
+/// exponent of the X (resp. Z) acting on the i-th qubit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pauli {
+    pub x: Vec<bool>,
+    pub z: Vec<bool>,
+}
+
+impl Pauli {
+    /// The Pauli with a single X (if `is_x`) or Z on `qubit`.
+    pub fn single(n_qubits: usize, qubit: usize, is_x: bool) -> Self {
+        let mut pauli = Pauli { x: vec![false; n_qubits], z: vec![false; n_qubits] };
+        match is_x {
+            true => pauli.x[qubit] = true,
+            false => pauli.z[qubit] = true,
+        }
+        pauli
+    }
+
+    /// Conjugate the Pauli by a gate, i.e. compute `U P U†`, up to a global
+    /// phase.
+    pub fn conjugate(&mut self, gate: CliffordGate, qubits: &[usize]) {
+        match gate {
+            CliffordGate::H => {
+                let q = qubits[0];
+                std::mem::swap(&mut self.x[q], &mut self.z[q]);
+            }
+            CliffordGate::S | CliffordGate::Sdg => {
+                let q = qubits[0];
+                self.z[q] ^= self.x[q];
+            }
+            CliffordGate::X | CliffordGate::Y | CliffordGate::Z => (),
+            CliffordGate::CX => {
+                let (c, t) = (qubits[0], qubits[1]);
+                self.x[t] ^= self.x[c];
+                self.z[c] ^= self.z[t];
+            }
+            CliffordGate::CZ => {
+                let (a, b) = (qubits[0], qubits[1]);
+                self.z[a] ^= self.x[b];
+                self.z[b] ^= self.x[a];
+            }
+        }
+    }
+}
+
+/// A Clifford circuit on a few qubits, given as a sequence of gates and the
+/// qubits each of them acts on.
+#[derive(Debug, Clone)]
+pub struct Clifford {
+    pub n_qubits: usize,
+    pub gates: Vec<(CliffordGate, Vec<usize>)>,
+}
+
+impl Clifford {
+    /// The circuit made of a single gate.
+    pub fn gate(gate: CliffordGate) -> Self {
+        let n_qubits = gate.n_qubits();
+        Clifford { n_qubits, gates: vec![(gate, (0..n_qubits).collect())] }
+    }
+
+    /// Conjugate a Pauli by the whole circuit.
+    pub fn conjugate(&self, pauli: &Pauli) -> Pauli {
+        let mut pauli = pauli.clone();
+        for (gate, qubits) in self.gates.iter() {
+            pauli.conjugate(*gate, qubits);
+        }
+        pauli
+    }
+
+    /// Generate the rules pushing each correction on the inputs of the circuit
+    /// through it.
+    ///
+    /// For each qubit and each of `CorrectionX` and `CorrectionZ`, the LHS is
+    /// the correction followed by the circuit, and the RHS is the circuit
+    /// followed by the corrections making up the conjugated Pauli, all of them
+    /// controlled by copies of the same signal. On each qubit, the X correction
+    /// is applied before the Z correction.
+    pub fn correction_rules(&self, registry: &ExtensionRegistry) -> Result<Vec<(Hugr, Hugr)>, BuildError> {
+        let mut rules = vec![];
+        for qubit in 0..self.n_qubits {
+            for is_x in [true, false] {
+                let pauli = Pauli::single(self.n_qubits, qubit, is_x);
+                let conjugated = self.conjugate(&pauli);
+                rules.push((self.corr_circuit(registry, &pauli)?, self.circuit_corr(registry, &conjugated)?));
+            }
+        }
+        Ok(rules)
+    }
+
+    /// The corrections for `pauli` followed by the circuit.
+    fn corr_circuit(&self, registry: &ExtensionRegistry, pauli: &Pauli) -> Result<Hugr, BuildError> {
+        let (mut h, mut qs, c) = self.builder(registry)?;
+        add_corrections(&mut h, registry, &mut qs, c, pauli)?;
+        self.add_gates(&mut h, &mut qs)?;
+        h.finish_hugr_with_outputs(qs, registry)
+    }
+
+    /// The circuit followed by the corrections for `pauli`.
+    fn circuit_corr(&self, registry: &ExtensionRegistry, pauli: &Pauli) -> Result<Hugr, BuildError> {
+        let (mut h, mut qs, c) = self.builder(registry)?;
+        self.add_gates(&mut h, &mut qs)?;
+        add_corrections(&mut h, registry, &mut qs, c, pauli)?;
+        h.finish_hugr_with_outputs(qs, registry)
+    }
+
+    /// A builder with a qubit input for each qubit of the circuit and a
+    /// `MyBool` input for the signal.
+    fn builder(&self, registry: &ExtensionRegistry) -> Result<(DFGBuilder<Hugr>, Vec<Wire>, Wire), BuildError> {
+        let extension = registry.get("ExtMBQC").unwrap();
+        let my_bool = Type::new_extension(extension.get_type("MyBool").unwrap().instantiate([]).unwrap());
+
+        let mut inputs = vec![QB_T; self.n_qubits];
+        inputs.push(my_bool);
+        let h = DFGBuilder::new(FunctionType::new(inputs, vec![QB_T; self.n_qubits]))?;
+        let mut qs: Vec<Wire> = h.input_wires().collect();
+        let c = qs.pop().unwrap();
+        Ok((h, qs, c))
+    }
+
+    fn add_gates(&self, h: &mut DFGBuilder<Hugr>, qs: &mut [Wire]) -> Result<(), BuildError> {
+        for (gate, qubits) in self.gates.iter() {
+            let res = h.add_dataflow_op(Tk2Op::from(*gate), qubits.iter().map(|q| qs[*q]))?;
+            for (i, q) in qubits.iter().enumerate() {
+                qs[*q] = res.out_wire(i);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add the corrections making up `pauli`, each controlled by a copy of `c`.
+fn add_corrections(
+    h: &mut DFGBuilder<Hugr>,
+    registry: &ExtensionRegistry,
+    qs: &mut [Wire],
+    c: Wire,
+    pauli: &Pauli,
+) -> Result<(), BuildError> {
+    // Load the extension
+    let extension = registry.get("ExtMBQC").unwrap();
+    let copy = extension.instantiate_extension_op("Copy", [], registry).unwrap();
+    let x_corr = extension.instantiate_extension_op("CorrectionX", [], registry).unwrap();
+    let z_corr = extension.instantiate_extension_op("CorrectionZ", [], registry).unwrap();
+
+    let mut corrections = vec![];
+    for q in 0..qs.len() {
+        if pauli.x[q] {
+            corrections.push((q, &x_corr));
+        }
+        if pauli.z[q] {
+            corrections.push((q, &z_corr));
+        }
+    }
+
+    // Make a copy of the signal for each correction but the last one
+    let mut c = c;
+    let n_corrections = corrections.len();
+    for (i, (q, corr)) in corrections.into_iter().enumerate() {
+        let signal = if i + 1 < n_corrections {
+            let res = h.add_dataflow_op(copy.clone(), [c])?;
+            c = res.out_wire(1);
+            res.out_wire(0)
+        } else {
+            c
+        };
+        let res = h.add_dataflow_op(corr.clone(), [qs[q], signal])?;
+        qs[q] = res.out_wire(0);
+    }
+    Ok(())
+}
+
+/// The correction-pushing rules for every Clifford gate in `gates`.
+pub fn correction_rules(gates: &[CliffordGate], registry: &ExtensionRegistry) -> Result<Vec<(Hugr, Hugr)>, BuildError> {
+    let mut rules = vec![];
+    for gate in gates {
+        rules.extend(Clifford::gate(*gate).correction_rules(registry)?);
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use hugr::HugrView;
+
+    use super::*;
+    use crate::test_utils::registry;
+    use crate::utils::op_name;
+
+    /// The corrections applied right before each output of `circ`, as a Pauli.
+    fn output_corrections(circ: &Hugr, n_qubits: usize) -> Pauli {
+        let [_, output] = circ.get_io(circ.root()).unwrap();
+        let mut pauli = Pauli { x: vec![false; n_qubits], z: vec![false; n_qubits] };
+        for q in 0..n_qubits {
+            let (mut node, _) = circ.single_linked_output(output, q).unwrap();
+            loop {
+                match op_name(circ, node).as_str() {
+                    "CorrectionX" => pauli.x[q] ^= true,
+                    "CorrectionZ" => pauli.z[q] ^= true,
+                    _ => break,
+                }
+                (node, _) = circ.single_linked_output(node, 0).unwrap();
+            }
+        }
+        pauli
+    }
+
+    #[test]
+    fn rules_follow_conjugation_tables() {
+        let reg = registry();
+        // For each gate, the Pauli `U P U†` for X then Z on each qubit, as the X and Z parts
+        let tables: [(CliffordGate, &[(&[bool], &[bool])]); 5] = [
+            (CliffordGate::H, &[(&[false], &[true]), (&[true], &[false])]),
+            (CliffordGate::S, &[(&[true], &[true]), (&[false], &[true])]),
+            (CliffordGate::Sdg, &[(&[true], &[true]), (&[false], &[true])]),
+            (
+                CliffordGate::CX,
+                &[
+                    (&[true, true], &[false, false]),
+                    (&[false, false], &[true, false]),
+                    (&[false, true], &[false, false]),
+                    (&[false, false], &[true, true]),
+                ],
+            ),
+            (
+                CliffordGate::CZ,
+                &[
+                    (&[true, false], &[false, true]),
+                    (&[false, false], &[true, false]),
+                    (&[false, true], &[true, false]),
+                    (&[false, false], &[false, true]),
+                ],
+            ),
+        ];
+        for (gate, table) in tables {
+            let rules = correction_rules(&[gate], &reg).unwrap();
+            assert_eq!(rules.len(), table.len(), "{gate:?}");
+            for ((_, rhs), (x, z)) in rules.iter().zip(table.iter()) {
+                let expected = Pauli { x: x.to_vec(), z: z.to_vec() };
+                assert_eq!(output_corrections(rhs, gate.n_qubits()), expected, "{gate:?}");
+            }
+        }
+    }
+}
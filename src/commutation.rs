@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+
+use hugr::{hugr::HugrMut, Hugr, HugrView, IncomingPort, Node, OutgoingPort};
+
+use crate::utils::{is_qubit_port, op_name, topological_order};
+
+/// The pairs of operations `(a, b)` such that, in the LHS, the qubit leaving
+/// a single-qubit operation `a` goes straight into `b`.
+pub fn lhs_adjacencies(lhs: &Hugr) -> Vec<(String, String)> {
+    let io = lhs.get_io(lhs.root()).unwrap();
+    let mut adjacencies = vec![];
+    for node in lhs.children(lhs.root()).filter(|n| !io.contains(n)) {
+        if !is_in_place(lhs, node) {
+            continue;
+        }
+        for (next, _) in lhs.linked_inputs(node, 0) {
+            if !io.contains(&next) {
+                adjacencies.push((op_name(lhs, node), op_name(lhs, next)));
+            }
+        }
+    }
+    adjacencies.sort();
+    adjacencies.dedup();
+    adjacencies
+}
+
+/// Whether `node` is a single-qubit operation acting on its qubit in place,
+/// i.e. its only output is the qubit that enters it through port 0.
+fn is_in_place(circ: &impl HugrView, node: Node) -> bool {
+    circ.num_outputs(node) == 1
+        && is_qubit_port(circ, node, OutgoingPort::from(0))
+        && is_qubit_port(circ, node, IncomingPort::from(0))
+}
+
+/// A single-qubit operation moved by `slide_commuting` right before the input
+/// port `port` of `target`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slide {
+    pub node: Node,
+    pub target: Node,
+    pub port: IncomingPort,
+}
+
+/// Slide single-qubit operations forward along their qubit wire, past the
+/// operations they commute with, so that they end up right before an operation
+/// they are adjacent to in the LHS of some rule.
+///
+/// Each operation `a` that appears first in one of the `adjacencies` is moved
+/// when the wire leaving it goes through single-qubit operations that commute
+/// with it (as listed in `commutations`, in either order) before reaching an
+/// operation `b` with `(a, b)` in `adjacencies`. Commutations are declared
+/// between operation names, which cannot tell which qubit of a multi-qubit
+/// operation they hold on, so the operations never slide past those. As the moved operations commute with those
+/// they slide past, the circuit is unchanged. This lets the rules match modulo
+/// commutation, without rules whose only purpose is to reorder operations.
+/// Returns the moves made, in order, so that they can be replayed with
+/// `apply_slide`.
+pub fn slide_commuting(
+    circ: &mut Hugr,
+    adjacencies: &[(String, String)],
+    commutations: &[(String, String)],
+) -> Vec<Slide> {
+    if commutations.is_empty() {
+        return vec![];
+    }
+    let wanted: HashSet<(&str, &str)> = adjacencies.iter().map(|(a, b)| (a.as_str(), b.as_str())).collect();
+    let commute = |a: &str, b: &str| commutations.iter().any(|(x, y)| (x == a && y == b) || (x == b && y == a));
+
+    let mut slides = vec![];
+    for node in topological_order(circ) {
+        let name = op_name(circ, node);
+        if !adjacencies.iter().any(|(a, _)| *a == name) || !is_in_place(circ, node) {
+            continue;
+        }
+        // Follow the qubit wire while the operations on it commute with the node
+        let (mut next, mut port) = circ.linked_inputs(node, 0).next().unwrap();
+        let mut skipped = 0;
+        loop {
+            let next_name = op_name(circ, next);
+            if skipped > 0 && wanted.contains(&(name.as_str(), next_name.as_str())) {
+                let slide = Slide { node, target: next, port };
+                apply_slide(circ, &slide);
+                slides.push(slide);
+                break;
+            }
+            if !commute(&name, &next_name) || !is_in_place(circ, next) {
+                break;
+            }
+            (next, port) = circ.linked_inputs(next, 0).next().unwrap();
+            skipped += 1;
+        }
+    }
+    slides
+}
+
+/// Take the single-qubit operation `slide.node` off its wire and put it back
+/// right before the input port `slide.port` of `slide.target`.
+pub fn apply_slide(circ: &mut Hugr, slide: &Slide) {
+    let Slide { node, target, port } = *slide;
+    // Reconnect the wire where the node was
+    let (prev, prev_port) = circ.single_linked_output(node, 0).unwrap();
+    let (next, next_port) = circ.linked_inputs(node, 0).next().unwrap();
+    circ.disconnect(node, IncomingPort::from(0)).unwrap();
+    circ.disconnect(node, OutgoingPort::from(0)).unwrap();
+    circ.connect(prev, prev_port, next, next_port).unwrap();
+
+    // Insert it before the target
+    let (source, source_port) = circ.single_linked_output(target, port).unwrap();
+    circ.disconnect(target, port).unwrap();
+    circ.connect(source, source_port, node, 0).unwrap();
+    circ.connect(node, 0, target, port).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::parse_circuit;
+    use crate::test_utils::registry;
+
+    /// Slide the `CorrectionX` of `source` towards an `H` gate, returning the
+    /// slides made and the name of the operation following the correction.
+    fn slide_correction(source: &str, commutations: &[(&str, &str)]) -> (Vec<Slide>, String) {
+        let mut circ = parse_circuit(source, &registry()).unwrap();
+        let pairs = |names: &[(&str, &str)]| names.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect::<Vec<_>>();
+        let slides = slide_commuting(&mut circ, &pairs(&[("CorrectionX", "H")]), &pairs(commutations));
+        let node = circ.nodes().find(|n| op_name(&circ, *n) == "CorrectionX").unwrap();
+        let (next, _) = circ.linked_inputs(node, 0).next().unwrap();
+        (slides, op_name(&circ, next))
+    }
+
+    #[test]
+    fn slide_past_commuting_op() {
+        let (slides, next) = slide_correction(
+            "q, a: MyBool, b: MyBool => CorrectionX(q, a); CorrectionZ(q, b); H(q)",
+            &[("CorrectionZ", "CorrectionX")],
+        );
+        assert_eq!(slides.len(), 1);
+        assert_eq!(slides[0].port, IncomingPort::from(0));
+        assert_eq!(next, "H");
+    }
+
+    #[test]
+    fn no_slide_past_non_commuting_op() {
+        let (slides, next) = slide_correction(
+            "q, a: MyBool => CorrectionX(q, a); S(q); H(q)",
+            &[("CorrectionX", "CorrectionZ")],
+        );
+        assert!(slides.is_empty());
+        assert_eq!(next, "S");
+    }
+
+    #[test]
+    fn no_slide_across_multi_qubit_op() {
+        let (slides, next) = slide_correction(
+            "q, r, a: MyBool => CorrectionX(q, a); CZ(q, r); H(q)",
+            &[("CorrectionX", "CZ")],
+        );
+        assert!(slides.is_empty());
+        assert_eq!(next, "CZ");
+    }
+}
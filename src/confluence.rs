@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use hugr::{
+    builder::{BuildError, DFGBuilder, Dataflow, DataflowHugr},
+    extension::ExtensionRegistry,
+    ops::OpTrait,
+    types::{FunctionType, Type},
+    Hugr, HugrView, Node, PortIndex, Wire,
+};
+use tket2::circuit::CircuitHash;
+
+use crate::rule_set::RuleSet;
+use crate::rules::Stage;
+use crate::utils::{apply_rule_set, op_name, RewriteConfig, RewriteError};
+
+/// Two rules whose LHS overlap, and what becomes of the smallest circuit where
+/// they overlap depending on which of them is applied.
+#[derive(Debug, Clone)]
+pub struct CriticalPair {
+    /// The indices of the two rules.
+    pub rules: (usize, usize),
+    /// The circuit made of the LHS of both rules, sharing some of their nodes.
+    pub overlap: Hugr,
+    /// The circuit obtained by applying each of the rules to the overlap, and
+    /// then the whole rule set until no more rules can be applied, or `None`
+    /// if the rule could not be applied to the overlap.
+    pub results: (Option<Result<Hugr, RewriteError>>, Option<Result<Hugr, RewriteError>>),
+}
+
+impl CriticalPair {
+    /// Whether both ways of rewriting the overlap lead to the same circuit.
+    pub fn is_joinable(&self) -> bool {
+        match &self.results {
+            (Some(Ok(a)), Some(Ok(b))) => a.circuit_hash() == b.circuit_hash(),
+            _ => false,
+        }
+    }
+}
+
+/// Find the critical pairs of the rules of a stage, i.e. the ways in which the
+/// LHS of two rules (or two copies of the same rule) can overlap, and check
+/// whether each of them can be joined using the stage itself.
+///
+/// The overlaps are found by identifying a node of one LHS with a node of the
+/// other one with the same operation, and then the nodes linked to them
+/// through the same ports, as long as both LHS have a node there. If the
+/// non-joinable pairs are empty the rules are locally confluent (on the
+/// overlaps found this way), so, if they terminate, the order in which they
+/// are applied does not matter. The results are compared after sliding
+/// operations through the commutations of the stage, so rules relying on them
+/// are joinable as long as they agree up to the order of commuting operations
+/// that the slides settle.
+pub fn critical_pairs(stage: &Stage, registry: &ExtensionRegistry) -> Vec<CriticalPair> {
+    let rules = &stage.rules;
+    let rule_set = RuleSet::from(stage.clone());
+    let config = RewriteConfig { incremental: false, ..RewriteConfig::default() };
+
+    let mut pairs = vec![];
+    for i in 0..rules.len() {
+        for j in i..rules.len() {
+            for map in overlaps(&rules[i].lhs, &rules[j].lhs, i == j) {
+                let Some(overlap) = glue(&rules[i].lhs, &rules[j].lhs, &map, registry) else {
+                    continue;
+                };
+                // Rewrite the overlap with each of the rules, at the nodes coming from its LHS.
+                // A rule that cannot be applied there makes the pair non-joinable.
+                let normalise = |mut circ: Hugr| apply_rule_set(&rule_set, &mut circ, &config, None).map(|_| circ);
+                let first = rewrite_at(&rule_set, &overlap.hugr, i, &overlap.first).map(normalise);
+                let second = rewrite_at(&rule_set, &overlap.hugr, j, &overlap.second).map(normalise);
+                pairs.push(CriticalPair { rules: (i, j), overlap: overlap.hugr, results: (first, second) });
+            }
+        }
+    }
+    pairs
+}
+
+/// A way of identifying nodes of the second LHS with nodes of the first one.
+type Overlap = BTreeMap<Node, Node>;
+
+/// The overlaps between `first` and `second`, each one grown from a pair of
+/// nodes with the same operation.
+fn overlaps(first: &Hugr, second: &Hugr, same_rule: bool) -> Vec<Overlap> {
+    let first_io = first.get_io(first.root()).unwrap();
+    let second_io = second.get_io(second.root()).unwrap();
+    let mut found = BTreeSet::new();
+    for a in first.children(first.root()).filter(|n| !first_io.contains(n)) {
+        for b in second.children(second.root()).filter(|n| !second_io.contains(n)) {
+            if op_name(first, a) != op_name(second, b) {
+                continue;
+            }
+            let Some(map) = unify(first, second, a, b) else {
+                continue;
+            };
+            // Overlapping a rule with itself entirely is not a critical pair
+            if same_rule && map.iter().all(|(x, y)| x == y) {
+                continue;
+            }
+            found.insert(map);
+        }
+    }
+    found.into_iter().collect()
+}
+
+/// Identify `b` in `second` with `a` in `first`, and then every pair of nodes
+/// linked to identified nodes through the same ports. Fails if such a pair
+/// has different operations or would identify a node with two others.
+///
+/// A copyable output, such as a classical signal, may feed any number of
+/// operations, so it does not force its consumers to be identified: both
+/// LHS may then use the same signal in different ways.
+fn unify(first: &Hugr, second: &Hugr, a: Node, b: Node) -> Option<Overlap> {
+    let first_io = first.get_io(first.root()).unwrap();
+    let second_io = second.get_io(second.root()).unwrap();
+    let mut map = Overlap::new();
+    let mut stack = vec![(b, a)];
+    while let Some((x, y)) = stack.pop() {
+        if let Some(&image) = map.get(&x) {
+            if image != y {
+                return None;
+            }
+            continue;
+        }
+        if op_name(second, x) != op_name(first, y) || map.values().any(|&v| v == y) {
+            return None;
+        }
+        map.insert(x, y);
+
+        let n_inputs = second.get_optype(x).dataflow_signature().unwrap().input().len();
+        for p in 0..n_inputs {
+            let (sx, sp) = second.single_linked_output(x, p).unwrap();
+            let (sy, sq) = first.single_linked_output(y, p).unwrap();
+            if second_io.contains(&sx) || first_io.contains(&sy) {
+                continue;
+            }
+            if sp != sq {
+                return None;
+            }
+            stack.push((sx, sy));
+        }
+        let outputs = second.get_optype(x).dataflow_signature().unwrap().output().clone();
+        for (p, ty) in outputs.iter().enumerate() {
+            if ty.copyable() {
+                continue;
+            }
+            let (Some((dx, dp)), Some((dy, dq))) = (second.linked_inputs(x, p).next(), first.linked_inputs(y, p).next()) else {
+                continue;
+            };
+            if second_io.contains(&dx) || first_io.contains(&dy) {
+                continue;
+            }
+            if dp != dq {
+                return None;
+            }
+            stack.push((dx, dy));
+        }
+    }
+    Some(map)
+}
+
+/// A node of the overlap, coming from either LHS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Part {
+    First(Node),
+    Second(Node),
+}
+
+/// The circuit made of both LHS glued along an overlap, along with the nodes
+/// coming from each of them.
+struct Glued {
+    hugr: Hugr,
+    first: Vec<Node>,
+    second: Vec<Node>,
+}
+
+/// Build the circuit made of `first` and `second`, where the nodes of
+/// `second` in `map` are replaced by their image in `first`. Returns `None` if
+/// the result would have a cycle.
+fn glue(first: &Hugr, second: &Hugr, map: &Overlap, registry: &ExtensionRegistry) -> Option<Glued> {
+    let first_io = first.get_io(first.root()).unwrap();
+    let second_io = second.get_io(second.root()).unwrap();
+    let part_of_second = |n: Node| match map.get(&n) {
+        Some(&m) => Part::First(m),
+        None => Part::Second(n),
+    };
+    let preimage: HashMap<Node, Node> = map.iter().map(|(x, y)| (*y, *x)).collect();
+
+    // The nodes of the overlap, and where each of their inputs comes from (`None` for an input of the circuit)
+    let mut parts: Vec<Part> = first.children(first.root()).filter(|n| !first_io.contains(n)).map(Part::First).collect();
+    parts.extend(
+        second
+            .children(second.root())
+            .filter(|n| !second_io.contains(n) && !map.contains_key(n))
+            .map(Part::Second),
+    );
+    let hugr_of = |part: Part| match part {
+        Part::First(n) => (first, n),
+        Part::Second(n) => (second, n),
+    };
+    let mut sources: HashMap<(Part, usize), Option<(Part, usize)>> = HashMap::new();
+    for &part in parts.iter() {
+        let (h, n) = hugr_of(part);
+        let n_inputs = h.get_optype(n).dataflow_signature().unwrap().input().len();
+        for p in 0..n_inputs {
+            let (s, sp) = h.single_linked_output(n, p).unwrap();
+            let source = match part {
+                Part::First(_) if s != first_io[0] => Some((Part::First(s), sp.index())),
+                // An input of the first LHS may be produced by a node of the second one
+                Part::First(_) => preimage.get(&n).and_then(|&x| {
+                    let (t, tp) = second.single_linked_output(x, p).unwrap();
+                    (t != second_io[0]).then(|| (part_of_second(t), tp.index()))
+                }),
+                Part::Second(_) if s != second_io[0] => Some((part_of_second(s), sp.index())),
+                Part::Second(_) => None,
+            };
+            sources.insert((part, p), source);
+        }
+    }
+
+    // Order the nodes topologically
+    let mut order = vec![];
+    let mut placed = BTreeSet::new();
+    while order.len() < parts.len() {
+        let ready = parts.iter().copied().find(|&part| {
+            !placed.contains(&part)
+                && sources.iter().all(|((q, _), source)| *q != part || source.map_or(true, |(s, _)| placed.contains(&s)))
+        })?;
+        placed.insert(ready);
+        order.push(ready);
+    }
+
+    // The inputs and outputs of the circuit are the ports left unconnected
+    let signature = |part: Part| {
+        let (h, n) = hugr_of(part);
+        h.get_optype(n).dataflow_signature().unwrap()
+    };
+    let mut inputs: Vec<(Part, usize)> = sources.iter().filter(|(_, s)| s.is_none()).map(|(k, _)| *k).collect();
+    inputs.sort();
+    let consumed: BTreeSet<(Part, usize)> = sources.values().flatten().copied().collect();
+    let mut outputs = vec![];
+    for &part in order.iter() {
+        for q in 0..signature(part).output().len() {
+            if !consumed.contains(&(part, q)) {
+                outputs.push((part, q));
+            }
+        }
+    }
+    let input_types: Vec<Type> = inputs.iter().map(|(part, p)| signature(*part).input().iter().nth(*p).unwrap().clone()).collect();
+    let output_types: Vec<Type> = outputs.iter().map(|(part, q)| signature(*part).output().iter().nth(*q).unwrap().clone()).collect();
+
+    let build = || -> Result<Glued, BuildError> {
+        let mut h = DFGBuilder::new(FunctionType::new(input_types.clone(), output_types.clone()))?;
+        let mut input_wires: HashMap<(Part, usize), Wire> = inputs.iter().copied().zip(h.input_wires()).collect();
+        let mut wires: HashMap<(Part, usize), Wire> = HashMap::new();
+        let mut nodes: HashMap<Part, Node> = HashMap::new();
+        for &part in order.iter() {
+            let (hugr, n) = hugr_of(part);
+            let n_inputs = signature(part).input().len();
+            let args: Vec<Wire> = (0..n_inputs)
+                .map(|p| match sources[&(part, p)] {
+                    Some(source) => wires[&source],
+                    None => input_wires.remove(&(part, p)).unwrap(),
+                })
+                .collect();
+            let res = h.add_dataflow_op(hugr.get_optype(n).clone(), args)?;
+            for q in 0..signature(part).output().len() {
+                wires.insert((part, q), res.out_wire(q));
+            }
+            nodes.insert(part, res.node());
+        }
+        let output_wires: Vec<Wire> = outputs.iter().map(|o| wires[o]).collect();
+        let hugr = h.finish_hugr_with_outputs(output_wires, registry)?;
+
+        let first_nodes = parts.iter().filter(|p| matches!(p, Part::First(_))).map(|p| nodes[p]).collect();
+        let second_nodes = second
+            .children(second.root())
+            .filter(|n| !second_io.contains(n))
+            .map(|n| nodes[&part_of_second(n)])
+            .collect();
+        Ok(Glued { hugr, first: first_nodes, second: second_nodes })
+    };
+    build().ok()
+}
+
+/// Apply rule `rule_id` to `circ` at the match covering exactly `nodes`, if
+/// there is one.
+fn rewrite_at(rule_set: &RuleSet, circ: &Hugr, rule_id: usize, nodes: &[Node]) -> Option<Hugr> {
+    let mut nodes = nodes.to_vec();
+    nodes.sort();
+    let m = rule_set.matcher.find_matches(circ).into_iter().find(|m| {
+        let mut matched = m.subcircuit().nodes().to_vec();
+        matched.sort();
+        m.pattern_id().0 == rule_id && matched == nodes
+    })?;
+    let rewrite = m.to_rewrite(circ, rule_set.rhs[rule_id].clone()).ok()?;
+    let mut circ = circ.clone();
+    rewrite.apply(&mut circ).ok()?;
+    Some(circ)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::parse_stages;
+    use crate::test_utils::registry;
+
+    #[test]
+    fn overlap_on_copied_signal() {
+        let reg = registry();
+        let source = "
+            copy_discard: a, b = Copy(c); DiscardSignal(a) => b = c
+            copy_xor: a, b = Copy(c); d = XOR(a, b) => d = ConstFalse(); DiscardSignal(c)
+        ";
+        let stages = parse_stages(source, &reg).unwrap();
+        let pairs = critical_pairs(&stages[0], &reg);
+        // The signals copied feed different operations in each LHS
+        let overlaps: Vec<&CriticalPair> = pairs.iter().filter(|pair| pair.rules == (0, 1)).collect();
+        assert!(!overlaps.is_empty());
+        // Removing the copy leaves an XOR of the same signal with itself, which
+        // no rule turns into the constant the other rule gives
+        assert!(overlaps.iter().all(|pair| !pair.is_joinable()));
+    }
+
+    #[test]
+    fn cancelling_pairs_are_joinable() {
+        let reg = registry();
+        let stages = parse_stages("h_h: H(q); H(q) =>", &reg).unwrap();
+        let pairs = critical_pairs(&stages[0], &reg);
+        assert!(!pairs.is_empty());
+        assert!(pairs.iter().all(CriticalPair::is_joinable));
+    }
+}
@@ -0,0 +1,685 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use hugr::{
+    extension::ExtensionRegistry,
+    hugr::HugrMut,
+    Hugr, HugrView, IncomingPort, Node, OutgoingPort,
+};
+
+use crate::pattern::diagonal_gate_angle;
+use crate::utils::{is_qubit_port, op_name, topological_order};
+
+/// A qubit of an MBQC pattern, i.e. a vertex of its underlying open graph.
+///
+/// The qubit lives on a single wire of the HUGR that starts either at the
+/// `Input` node or at a `PrepPlus` and ends either at the `Output` node or at a
+/// `MeasureX`. Every operation in between acts on the qubit "in place", i.e.
+/// the qubit enters and leaves the operation through ports with the same index.
+#[derive(Debug, Clone)]
+pub struct Vertex {
+    /// The node and port where the qubit wire starts.
+    pub start: (Node, OutgoingPort),
+    /// The node and port where the qubit wire ends.
+    pub end: (Node, IncomingPort),
+    /// The nodes acting on the qubit, in the order they are applied, along with
+    /// the index of the port the qubit goes through.
+    pub path: Vec<(Node, usize)>,
+}
+
+impl Vertex {
+    /// Whether the qubit was prepared in the pattern (rather than being an input).
+    pub fn is_prepared(&self, circ: &Hugr) -> bool {
+        op_name(circ, self.start.0) == "PrepPlus"
+    }
+
+    /// Whether the qubit is measured (rather than being an output).
+    pub fn is_measured(&self, circ: &Hugr) -> bool {
+        op_name(circ, self.end.0) == "MeasureX"
+    }
+
+    /// Whether every node acting on the qubit is a `CZ`.
+    pub fn only_cz(&self, circ: &Hugr) -> bool {
+        self.path.iter().all(|(n, _)| op_name(circ, *n) == "CZ")
+    }
+
+    /// The basis the qubit is measured in, given by the diagonal gates applied
+    /// before its `MeasureX`. Pauli gates and corrections only negate the
+    /// angle or shift it by π, which keeps the basis. Returns `None` if the
+    /// qubit is not measured or if some other gate (e.g. `H`) acts on it.
+    pub fn basis(&self, circ: &Hugr) -> Option<Basis> {
+        if !self.is_measured(circ) {
+            return None;
+        }
+        let mut angle = 0.0;
+        for (n, _) in self.path.iter() {
+            let name = op_name(circ, *n);
+            match (name.as_str(), diagonal_gate_angle(&name)) {
+                (_, Some(phi)) => angle += phi,
+                ("CZ" | "X" | "Y" | "CorrectionX" | "CorrectionZ", _) => (),
+                _ => return None,
+            }
+        }
+        let half_turns = f64::rem_euclid(angle, 1.0);
+        let near = |a: f64| (half_turns - a).abs() < 1e-9;
+        if near(0.0) || near(1.0) {
+            Some(Basis::X)
+        } else if near(0.5) {
+            Some(Basis::Y)
+        } else {
+            Some(Basis::XY)
+        }
+    }
+}
+
+/// The basis of a measurement in the XY plane of the Bloch sphere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Basis {
+    /// The X basis, at angle 0 or π.
+    X,
+    /// The Y basis, at angle ±π/2.
+    Y,
+    /// Any other angle, which is not a Pauli measurement.
+    XY,
+}
+
+/// The open graph underlying an MBQC pattern.
+///
+/// Each qubit of the pattern is a vertex and each `CZ` gate is an edge. Since
+/// a pair of `CZ` gates on the same qubits cancels out, the adjacency of two
+/// vertices is the parity of the number of `CZ` gates between them. The
+/// measurements produced by `to_mbqc` are X-basis measurements, while those of
+/// a pattern built by `Pattern::to_hugr` are preceded by diagonal gates giving
+/// their angle, so the basis of each measured vertex is stored too.
+#[derive(Debug, Clone)]
+pub struct OpenGraph {
+    pub vertices: Vec<Vertex>,
+    /// Each `CZ` node in the pattern along with the two vertices it acts on.
+    pub edges: Vec<(Node, usize, usize)>,
+    /// Neighbours of each vertex.
+    pub adjacency: Vec<BTreeSet<usize>>,
+    pub inputs: BTreeSet<usize>,
+    pub outputs: BTreeSet<usize>,
+    /// The measured vertices.
+    pub measured: BTreeSet<usize>,
+    /// The basis of each measured vertex, or `None` for the outputs and for
+    /// the measured vertices with an unsupported basis.
+    pub bases: Vec<Option<Basis>>,
+}
+
+impl OpenGraph {
+    /// Extract the open graph from a HUGR built out of `ExtMBQC` operations and
+    /// `Tk2Op` gates acting on a single qubit or on a pair of qubits via `CZ`.
+    ///
+    /// Input qubits are numbered first (in the order of the input ports) and
+    /// prepared qubits are numbered afterwards, in topological order.
+    pub fn from_hugr(circ: &Hugr) -> Self {
+        let [input, _] = circ.get_io(circ.root()).unwrap();
+
+        // Find where each of the qubit wires starts
+        let mut starts = vec![];
+        let mut inputs = BTreeSet::new();
+        for p in circ.node_outputs(input) {
+            if circ.linked_ports(input, p).count() > 0 && is_qubit_port(circ, input, p) {
+                inputs.insert(starts.len());
+                starts.push((input, p));
+            }
+        }
+        for node in topological_order(circ) {
+            if op_name(circ, node) == "PrepPlus" {
+                starts.push((node, OutgoingPort::from(0)));
+            }
+        }
+
+        // Follow each wire until it reaches either a measurement or the output
+        let mut vertices = vec![];
+        let mut outputs = BTreeSet::new();
+        let mut measured = BTreeSet::new();
+        let mut cz_ports: BTreeMap<Node, Vec<usize>> = BTreeMap::new();
+        for (v, &start) in starts.iter().enumerate() {
+            let mut path = vec![];
+            let (mut node, mut port) = start;
+            let end = loop {
+                let (next, next_port) = circ.linked_inputs(node, port).next().unwrap();
+                match op_name(circ, next).as_str() {
+                    "Output" => {
+                        outputs.insert(v);
+                        break (next, next_port);
+                    }
+                    "MeasureX" => {
+                        measured.insert(v);
+                        break (next, next_port);
+                    }
+                    "CZ" => cz_ports.entry(next).or_default().push(v),
+                    _ => (),
+                }
+                path.push((next, next_port.index()));
+                node = next;
+                port = OutgoingPort::from(next_port.index());
+            };
+            vertices.push(Vertex { start, end, path });
+        }
+
+        // Each `CZ` toggles the adjacency between the two qubits it acts on
+        let mut edges = vec![];
+        let mut adjacency = vec![BTreeSet::new(); vertices.len()];
+        for (cz, vs) in cz_ports {
+            let (u, w) = (vs[0], vs[1]);
+            if !adjacency[u].remove(&w) {
+                adjacency[u].insert(w);
+            }
+            if !adjacency[w].remove(&u) {
+                adjacency[w].insert(u);
+            }
+            edges.push((cz, u, w));
+        }
+
+        let bases = vertices.iter().map(|vertex| vertex.basis(circ)).collect();
+        OpenGraph { vertices, edges, adjacency, inputs, outputs, measured, bases }
+    }
+
+    /// The odd neighbourhood of a set of vertices, i.e. the vertices that are
+    /// adjacent to an odd number of vertices in the set.
+    pub fn odd_neighbourhood(&self, set: &BTreeSet<usize>) -> BTreeSet<usize> {
+        let mut odd = BTreeSet::new();
+        for v in set {
+            for w in self.adjacency[*v].iter() {
+                if !odd.remove(w) {
+                    odd.insert(*w);
+                }
+            }
+        }
+        odd
+    }
+
+    /// The `CZ` nodes acting on both `u` and `w`.
+    pub fn czs_between(&self, u: usize, w: usize) -> Vec<Node> {
+        self.edges
+            .iter()
+            .filter(|(_, a, b)| (*a == u && *b == w) || (*a == w && *b == u))
+            .map(|(cz, _, _)| *cz)
+            .collect()
+    }
+
+    /// Whether some correction set of the measured vertex `v` X-corrects
+    /// exactly the outputs in `xs` and Z-corrects exactly the outputs in `zs`,
+    /// without affecting the outcome of any other measurement, i.e. whether
+    /// applying these corrections is how the pattern undoes an undesired
+    /// outcome on `v` (see `find_pauli_flow` for the conditions on the other
+    /// measured vertices).
+    pub fn is_correction_of(&self, v: usize, xs: &BTreeSet<usize>, zs: &BTreeSet<usize>) -> bool {
+        let Some((cols, mut matrix, mut target)) = self.flow_system(&self.measured, v) else {
+            return false;
+        };
+        // The outputs in the set are X-corrected, those in its odd neighbourhood Z-corrected
+        for o in self.outputs.iter() {
+            matrix.push(cols.iter().map(|c| c == o).collect());
+            target.push(xs.contains(o));
+            matrix.push(cols.iter().map(|c| self.adjacency[*o].contains(c)).collect());
+            target.push(zs.contains(o));
+        }
+        solve_gf2(&matrix, &target).is_some()
+    }
+
+    /// The linear system over GF(2) whose solutions are the correction sets
+    /// of `v` when the vertices in `unsolved` are not measured after it: the
+    /// columns that may be in the set, and a row for each vertex in
+    /// `unsolved` with its target. Returns `None` if the basis of one of
+    /// these vertices is not supported.
+    fn flow_system(&self, unsolved: &BTreeSet<usize>, v: usize) -> Option<(Vec<usize>, Vec<Vec<bool>>, Vec<bool>)> {
+        let bases: Vec<Basis> = unsolved.iter().map(|u| self.bases[*u]).collect::<Option<_>>()?;
+        let basis = |u: usize| unsolved.iter().position(|w| *w == u).map(|k| bases[k]);
+        // A vertex measured in the XY plane must not be X-corrected before it is measured
+        let cols: Vec<usize> = (0..self.vertices.len())
+            .filter(|c| !self.inputs.contains(c) && basis(*c) != Some(Basis::XY))
+            .collect();
+        let mut matrix = vec![];
+        let mut target = vec![];
+        for (&u, b) in unsolved.iter().zip(bases) {
+            // A Y measurement is only affected by an X or a Z correction on its own, not by both
+            matrix.push(cols.iter().map(|c| self.adjacency[u].contains(c) ^ (b == Basis::Y && *c == u)).collect());
+            target.push(u == v);
+        }
+        Some((cols, matrix, target))
+    }
+/// A Pauli flow of an open graph whose measurements are in the XY plane.
+///
+/// For each measured vertex `v`, `correction_sets[v]` is the set of non-input
+/// vertices whose X-correction (together with the Z-corrections on their
+/// neighbours) undoes the effect of an undesired outcome on `v`, and
+/// `layers[v]` orders the measurements: the vertices in a higher layer are
+/// measured first, and those in the same layer can be measured together.
+#[derive(Debug, Clone)]
+pub struct PauliFlow {
+    pub correction_sets: BTreeMap<usize, BTreeSet<usize>>,
+    pub layers: BTreeMap<usize, usize>,
+}
+
+/// Find a Pauli flow of `graph`, if there is one.
+///
+/// This follows the layered search for a maximally delayed Pauli flow
+/// (Simmons 2021, "Relating measurement patterns to circuits via Pauli flow";
+/// Mitosek and Backens 2024, "An algebraic interpretation of Pauli flow"),
+/// which finds the vertices that can be measured last, then those before them,
+/// and so on. A measured vertex `v` can be measured before the vertices solved
+/// so far if it has a correction set `p(v)` made of non-input vertices such
+/// that, among the vertices not solved yet,
+/// - none measured in the X basis or in the XY plane other than `v` is in the
+///   odd neighbourhood of `p(v)`, and `v` itself is;
+/// - each one measured in the Y basis is either in both `p(v)` and its odd
+///   neighbourhood or in neither, except for `v` which is in exactly one;
+/// - none measured in the XY plane is in `p(v)`.
+///
+/// The vertices solved before are measured later, so the corrections may act
+/// on them freely. For each vertex, this is a linear system over GF(2), which
+/// we solve via Gaussian elimination.
+///
+/// Returns `None` if at some point none of the remaining vertices can be
+/// solved, in which case the pattern has no Pauli flow, or if some vertex is
+/// measured in an unsupported basis.
+pub fn find_pauli_flow(graph: &OpenGraph) -> Option<PauliFlow> {
+    let mut unsolved = graph.measured.clone();
+    let mut correction_sets = BTreeMap::new();
+    let mut layers = BTreeMap::new();
+    let mut layer = 0;
+    while !unsolved.is_empty() {
+        let mut solved = vec![];
+        for &v in unsolved.iter() {
+            let (cols, matrix, target) = graph.flow_system(&unsolved, v)?;
+            let Some(solution) = solve_gf2(&matrix, &target) else {
+                continue;
+            };
+            let set: BTreeSet<usize> = cols
+                .iter()
+                .zip(solution)
+                .filter(|(_, b)| *b)
+                .map(|(c, _)| *c)
+                .collect();
+            solved.push((v, set));
+        }
+        if solved.is_empty() {
+            return None;
+        }
+        for (v, set) in solved {
+            unsolved.remove(&v);
+            correction_sets.insert(v, set);
+            layers.insert(v, layer);
+        }
+        layer += 1;
+    }
+    Some(PauliFlow { correction_sets, layers })
+}
+
+/// Solve the linear system `matrix * x = target` over GF(2).
+fn solve_gf2(matrix: &[Vec<bool>], target: &[bool]) -> Option<Vec<bool>> {
+    let n_cols = matrix.first().map_or(0, |row| row.len());
+    // Augmented matrix [matrix | target]
+    let mut rows: Vec<Vec<bool>> = matrix
+        .iter()
+        .zip(target)
+        .map(|(row, b)| row.iter().copied().chain([*b]).collect())
+        .collect();
+
+    // Reduce to row echelon form, remembering the pivot column of each row
+    let mut pivots = vec![];
+    let mut r = 0;
+    for c in 0..n_cols {
+        let Some(p) = (r..rows.len()).find(|&i| rows[i][c]) else {
+            continue;
+        };
+        rows.swap(r, p);
+        for i in 0..rows.len() {
+            if i != r && rows[i][c] {
+                let pivot_row = rows[r].clone();
+                for (x, y) in rows[i].iter_mut().zip(pivot_row) {
+                    *x ^= y;
+                }
+            }
+        }
+        pivots.push(c);
+        r += 1;
+    }
+
+    // The system is inconsistent if a zero row has a non-zero target
+    if rows[r..].iter().any(|row| row[n_cols]) {
+        return None;
+    }
+    let mut solution = vec![false; n_cols];
+    for (i, c) in pivots.into_iter().enumerate() {
+        solution[c] = rows[i][n_cols];
+    }
+    Some(solution)
+}
+
+/// A chain `u - v - w` of vertices that can be removed from the pattern.
+///
+/// Both `u` and `v` are measured in the X basis and `v` is only connected to
+/// `u` and `w`, so that the chain simply teleports the state of `u` onto `w`
+/// via `H·H`, i.e. the identity up to the byproduct `X^{s_v} Z^{s_u}` on `w`.
+/// Hence, `v` can be removed and `u` and `w` merged into a single qubit, as
+/// long as the outcomes `s_u` and `s_v` are only used to undo byproducts.
+struct Chain {
+    u: usize,
+    v: usize,
+    w: usize,
+}
+
+/// Find a chain of Pauli-measured vertices that can be removed.
+fn find_chain(circ: &Hugr, graph: &OpenGraph) -> Option<Chain> {
+    for &v in graph.measured.iter() {
+        let vertex = &graph.vertices[v];
+        // `v` must be an ancilla whose only operations are two `CZ` gates
+        if !vertex.is_prepared(circ) || !vertex.only_cz(circ) || vertex.path.len() != 2 {
+            continue;
+        }
+        let neighbours: Vec<usize> = graph.adjacency[v].iter().copied().collect();
+        if neighbours.len() != 2 {
+            continue;
+        }
+        for (u, w) in [(neighbours[0], neighbours[1]), (neighbours[1], neighbours[0])] {
+            // The state of `u` must only be affected by `CZ` gates before it is measured
+            if !graph.vertices[u].is_measured(circ) || !graph.vertices[u].only_cz(circ) {
+                continue;
+            }
+            // `w` must be an ancilla whose operations before its `CZ` with `v` commute with it
+            let w_vertex = &graph.vertices[w];
+            if !w_vertex.is_prepared(circ) {
+                continue;
+            }
+            let cz_vw = graph.czs_between(v, w)[0];
+            let before_cz_vw = w_vertex.path.iter().take_while(|(n, _)| *n != cz_vw);
+            if before_cz_vw.clone().any(|(n, _)| op_name(circ, *n) != "CZ") {
+                continue;
+            }
+            // Merging `u` and `w` would create a `CZ` acting twice on the same qubit
+            if !graph.czs_between(u, w).is_empty() {
+                continue;
+            }
+            if creates_cycle(circ, graph, &Chain { u, v, w }) {
+                continue;
+            }
+            // The outcomes of `u` and `v` must only drive the corrections given by their correction sets
+            let follows_flow = [u, v].into_iter().all(|x| {
+                outcome_corrections(circ, graph, graph.vertices[x].end.0)
+                    .is_some_and(|(xs, zs)| graph.is_correction_of(x, &xs, &zs))
+            });
+            if !follows_flow {
+                continue;
+            }
+            return Some(Chain { u, v, w });
+        }
+    }
+    None
+}
+
+/// The corrections driven by the outcome of the measurement `meas`, as the
+/// output vertices it X-corrects and those it Z-corrects.
+///
+/// The outcome is followed through `Copy` and `XOR` nodes, so that a correction
+/// whose signal contains it an even number of times does not depend on it. The
+/// corrections are read as acting right after the last `CZ` of their wire: a
+/// `CorrectionX` applied after an odd number of `S` or `Sdg` gates undoes both
+/// an X and a Z byproduct. Returns `None` if the outcome is used by anything
+/// else than corrections on output wires after their last `CZ`.
+fn outcome_corrections(circ: &Hugr, graph: &OpenGraph, meas: Node) -> Option<(BTreeSet<usize>, BTreeSet<usize>)> {
+    // Whether each signal depends on the outcome
+    let mut depends: HashMap<(Node, OutgoingPort), bool> = HashMap::from([((meas, OutgoingPort::from(0)), true)]);
+    let mut corrections = vec![];
+    for node in topological_order(circ) {
+        let inputs: Vec<bool> = circ
+            .node_inputs(node)
+            .map(|p| {
+                circ.single_linked_output(node, p)
+                    .and_then(|source| depends.get(&source).copied())
+                    .unwrap_or(false)
+            })
+            .collect();
+        if !inputs.contains(&true) {
+            continue;
+        }
+        match op_name(circ, node).as_str() {
+            "Copy" => {
+                depends.insert((node, OutgoingPort::from(0)), true);
+                depends.insert((node, OutgoingPort::from(1)), true);
+            }
+            "XOR" => {
+                depends.insert((node, OutgoingPort::from(0)), inputs[0] ^ inputs[1]);
+            }
+            "CorrectionX" | "CorrectionZ" => corrections.push(node),
+            "DiscardSignal" => (),
+            _ => return None,
+        }
+    }
+
+    let mut xs = BTreeSet::new();
+    let mut zs = BTreeSet::new();
+    for correction in corrections {
+        let o = graph
+            .outputs
+            .iter()
+            .copied()
+            .find(|o| graph.vertices[*o].path.iter().any(|(n, _)| *n == correction))?;
+        let path = &graph.vertices[o].path;
+        let at = path.iter().position(|(n, _)| *n == correction).unwrap();
+        if path[at..].iter().any(|(n, _)| op_name(circ, *n) == "CZ") {
+            return None;
+        }
+        // Conjugating X by `S` or `Sdg` gives Y, i.e. both an X and a Z byproduct
+        let mut conjugated = false;
+        for (n, _) in path[..at].iter().rev() {
+            match op_name(circ, *n).as_str() {
+                "CZ" => break,
+                "S" | "Sdg" => conjugated = !conjugated,
+                "X" | "Y" | "Z" | "CorrectionX" | "CorrectionZ" => (),
+                _ => return None,
+            }
+        }
+        let mut toggled = vec![];
+        if op_name(circ, correction) == "CorrectionZ" {
+            toggled.push(&mut zs);
+        } else if conjugated {
+            toggled.extend([&mut xs, &mut zs]);
+        } else {
+            toggled.push(&mut xs);
+        }
+        for set in toggled {
+            if !set.remove(&o) {
+                set.insert(o);
+            }
+        }
+    }
+    Some((xs, zs))
+}
+
+/// Whether merging the wires of `u` and `w` would introduce a cycle in the HUGR.
+///
+/// This is the case if any of the operations acting on `w` (or any of the
+/// operations depending on them) is a dependency of the measurement of `u`.
+fn creates_cycle(circ: &Hugr, graph: &OpenGraph, chain: &Chain) -> bool {
+    let (u, v, w) = (&graph.vertices[chain.u], &graph.vertices[chain.v], &graph.vertices[chain.w]);
+    // The nodes that will be removed, which we must not traverse
+    let mut removed: HashSet<Node> = v.path.iter().map(|(n, _)| *n).collect();
+    removed.extend([v.start.0, v.end.0, w.start.0]);
+
+    let mut stack: Vec<Node> = w.path.iter().map(|(n, _)| *n).filter(|n| !removed.contains(n)).collect();
+    let mut visited = HashSet::new();
+    while let Some(node) = stack.pop() {
+        if node == u.end.0 {
+            return true;
+        }
+        if !visited.insert(node) {
+            continue;
+        }
+        for p in circ.node_outputs(node) {
+            for (next, _) in circ.linked_ports(node, p) {
+                if !removed.contains(&next) {
+                    stack.push(next);
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Remove the `CZ` acting on the port `port` of a wire, reconnecting the wire.
+fn remove_cz_from_wire(circ: &mut Hugr, cz: Node, port: usize) {
+    let (prev, prev_port) = circ.single_linked_output(cz, port).unwrap();
+    let (next, next_port) = circ.linked_inputs(cz, port).next().unwrap();
+    circ.remove_node(cz).unwrap();
+    circ.connect(prev, prev_port, next, next_port).unwrap();
+}
+
+/// Replace the outcome of `meas` with constant false signals.
+fn replace_outcome_with_false(circ: &mut Hugr, meas: Node, reg: &ExtensionRegistry) {
+    let const_false = reg
+        .get("ExtMBQC")
+        .unwrap()
+        .instantiate_extension_op("ConstFalse", [], reg)
+        .unwrap();
+    let targets: Vec<(Node, IncomingPort)> = circ.linked_inputs(meas, 0).collect();
+    for (target, port) in targets {
+        circ.disconnect(target, port).unwrap();
+        let f = circ.add_op_with_parent(circ.root(), const_false.clone()).unwrap();
+        circ.connect(f, 0, target, port).unwrap();
+    }
+}
+
+/// Remove the chain `u - v - w` from the pattern, merging `u` and `w`.
+///
+/// The outcomes of `u` and `v` only drive the corrections of their correction
+/// sets (see `find_chain`), which undo the byproduct left by the chain. Without
+/// the chain there is no byproduct to undo, so the qubit wire of `u` continues
+/// as the qubit wire of `w` and every correction depending on the outcomes of
+/// `u` or `v` receives a constant false signal instead.
+fn remove_chain(circ: &mut Hugr, graph: &OpenGraph, chain: &Chain, reg: &ExtensionRegistry) {
+    let (u, v, w) = (&graph.vertices[chain.u], &graph.vertices[chain.v], &graph.vertices[chain.w]);
+    let cz_uv = graph.czs_between(chain.u, chain.v)[0];
+    let cz_vw = graph.czs_between(chain.v, chain.w)[0];
+    let u_port = u.path.iter().find(|(n, _)| *n == cz_uv).unwrap().1;
+    let w_port = w.path.iter().find(|(n, _)| *n == cz_vw).unwrap().1;
+
+    // Remove the outcomes of the measurements on `u` and `v`
+    replace_outcome_with_false(circ, u.end.0, reg);
+    replace_outcome_with_false(circ, v.end.0, reg);
+
+    // Remove `v` from the pattern
+    remove_cz_from_wire(circ, cz_uv, u_port);
+    remove_cz_from_wire(circ, cz_vw, w_port);
+    circ.remove_node(v.start.0).unwrap();
+    circ.remove_node(v.end.0).unwrap();
+
+    // Merge the wires of `u` and `w`
+    let (last, last_port) = circ.single_linked_output(u.end.0, u.end.1).unwrap();
+    let (first, first_port) = circ.linked_inputs(w.start.0, w.start.1).next().unwrap();
+    circ.remove_node(u.end.0).unwrap();
+    circ.remove_node(w.start.0).unwrap();
+    circ.connect(last, last_port, first, first_port).unwrap();
+}
+
+/// Remove chains of Pauli-measured qubits from the pattern in `circ`.
+///
+/// This only acts on patterns that have a Pauli flow, i.e. deterministic
+/// patterns, which is checked again after each chain is removed (see
+/// `find_pauli_flow`). It is meant
+/// to be applied after `propagate_corrections`, once all corrections have been
+/// removed from measured qubits. Only chains whose outcomes drive exactly the
+/// corrections of a correction set are removed, each of them eliminating two
+/// qubits from the pattern. The measurement outcomes of the removed qubits are
+/// replaced by `ConstFalse` signals, which should then be simplified away (see
+/// `remove_pauli_measurements` in `rewrites.rs`).
+///
+/// Returns the number of qubits removed.
+pub fn remove_pauli_chains(circ: &mut Hugr, reg: &ExtensionRegistry) -> usize {
+    let mut removed = 0;
+    loop {
+        // Vertices are renumbered after each removal, so the graph is extracted again
+        let graph = OpenGraph::from_hugr(circ);
+        if find_pauli_flow(&graph).is_none() {
+            break;
+        }
+        let Some(chain) = find_chain(circ, &graph) else {
+            break;
+        };
+        remove_chain(circ, &graph, &chain, reg);
+        removed += 2;
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Pattern;
+    use crate::test_utils::registry;
+
+    /// The chain `0 - 1 - 2` teleporting the input onto the output, with the
+    /// corrections given by its flow unless `swapped`.
+    fn chain_pattern(swapped: bool) -> Pattern {
+        let (x, z) = if swapped { (0, 1) } else { (1, 0) };
+        format!("I 0\nO 2\nN 1\nN 2\nE 0 1\nE 1 2\nM 0 0\nM 1 0\nX 2 [{x}]\nZ 2 [{z}]")
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn solve_gf2_finds_solution() {
+        let matrix = vec![vec![true, true, false], vec![false, true, true]];
+        let solution = solve_gf2(&matrix, &[true, false]).unwrap();
+        for (row, b) in matrix.iter().zip([true, false]) {
+            let value = row.iter().zip(solution.iter()).fold(false, |acc, (a, x)| acc ^ (*a && *x));
+            assert_eq!(value, b);
+        }
+    }
+
+    #[test]
+    fn solve_gf2_detects_inconsistency() {
+        let matrix = vec![vec![true, true], vec![true, true]];
+        assert_eq!(solve_gf2(&matrix, &[true, false]), None);
+        assert_eq!(solve_gf2(&matrix, &[true, true]), Some(vec![true, false]));
+    }
+
+    #[test]
+    fn chain_has_pauli_flow() {
+        let reg = registry();
+        let circ = chain_pattern(false).to_hugr(&reg).unwrap();
+        let graph = OpenGraph::from_hugr(&circ);
+        let flow = find_pauli_flow(&graph).unwrap();
+        for (v, set) in flow.correction_sets.iter() {
+            assert!(graph.odd_neighbourhood(set).intersection(&graph.measured).eq([v]));
+        }
+        assert!(flow.layers.values().all(|l| *l == 0));
+    }
+
+    #[test]
+    fn y_measurement_corrects_itself() {
+        let reg = registry();
+        let pattern: Pattern = "I 0\nO 0\nN 1\nM 1 0.5".parse().unwrap();
+        let graph = OpenGraph::from_hugr(&pattern.to_hugr(&reg).unwrap());
+        assert_eq!(graph.bases[1], Some(Basis::Y));
+        // No set has the isolated vertex in its odd neighbourhood ...
+        let cols: Vec<usize> = (1..graph.vertices.len()).collect();
+        let row = cols.iter().map(|c| graph.adjacency[1].contains(c)).collect();
+        assert_eq!(solve_gf2(&[row], &[true]), None);
+        // ... but X-correcting it undoes the outcome of a Y measurement
+        let flow = find_pauli_flow(&graph).unwrap();
+        assert!(flow.correction_sets[&1].iter().eq([&1]));
+    }
+
+    #[test]
+    fn remove_chain_following_flow() {
+        let reg = registry();
+        let mut circ = chain_pattern(false).to_hugr(&reg).unwrap();
+        assert_eq!(remove_pauli_chains(&mut circ, &reg), 2);
+        let graph = OpenGraph::from_hugr(&circ);
+        assert_eq!(graph.vertices.len(), 1);
+        assert!(graph.measured.is_empty());
+        assert!(graph.edges.is_empty());
+    }
+
+    #[test]
+    fn keep_chain_with_other_corrections() {
+        let reg = registry();
+        let mut circ = chain_pattern(true).to_hugr(&reg).unwrap();
+        assert_eq!(remove_pauli_chains(&mut circ, &reg), 0);
+        assert_eq!(OpenGraph::from_hugr(&circ).vertices.len(), 3);
+    }
+}
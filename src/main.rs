@@ -1,70 +1,104 @@
+use std::fmt::Display;
 use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
 use hugr::{
-    builder::{BuildError, DFGBuilder, Dataflow, DataflowHugr}, 
+    builder::BuildError,
     extension::{
-        declarative::load_extensions_file, prelude::QB_T, PRELUDE_REGISTRY
+        declarative::load_extensions_file, ExtensionRegistry, PRELUDE_REGISTRY
     }, 
-    types::FunctionType, Hugr
+    Hugr
 };
-use tket2::Tk2Op;
 
 mod utils;
-mod patterns;
+mod clifford;
+mod rules;
+mod rule_set;
+mod selection;
+mod commutation;
 mod rewrites;
-use crate::utils::viz_hugr;
+mod flow;
+mod pattern;
+mod trace;
+mod confluence;
+mod termination;
+mod search;
+#[cfg(test)]
+mod test_utils;
+use crate::utils::{apply_stages_with_config, viz_hugr, RewriteError};
+use crate::trace::RewriteTrace;
+use crate::rules::{load_stages, Stage};
+use crate::confluence::critical_pairs;
+use crate::termination::check_termination;
+use crate::search::{optimise, MbqcCost, SearchConfig};
+use crate::rule_set::RuleSet;
+use crate::pattern::Pattern;
 use crate::rewrites::{
+    MbqcRules,
+    pass_stages,
+    cx_to_cz,
+    reduce_hadamards,
+    absorb_paulis,
     to_mbqc,
     push_corrections_and_s_gates,
     propagate_corrections,
+    remove_pauli_measurements,
     prep_to_alloc,
 };
 
 
 fn circ_example() -> Result<Hugr, BuildError> {
-    let mut h = DFGBuilder::new(FunctionType::new(vec![QB_T; 4], vec![QB_T; 4]))?;
-
-    let mut inps = h.input_wires();
-    let q0 = inps.next().unwrap();
-    let q1 = inps.next().unwrap();
-    let q2 = inps.next().unwrap();
-    let q3 = inps.next().unwrap();
-
-    let res = h.add_dataflow_op(Tk2Op::H, [q3])?;
-    let q3 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::CZ, [q2, q3])?;
-    let q2 = res.out_wire(0);
-    let q3 = res.out_wire(1);
-    let res = h.add_dataflow_op(Tk2Op::S, [q3])?;
-    let q3 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::H, [q3])?;
-    let q3 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::H, [q0])?;
-    let q0 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::S, [q1])?;
-    let q1 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::S, [q2])?;
-    let q2 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::CZ, [q1, q2])?;
-    let q1 = res.out_wire(0);
-    let q2 = res.out_wire(1);
-    let res = h.add_dataflow_op(Tk2Op::S, [q3])?;
-    let q3 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::H, [q2])?;
-    let q2 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::S, [q2])?;
-    let q2 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::H, [q1])?;
-    let q1 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::S, [q0])?;
-    let q0 = res.out_wire(0);
-    let res = h.add_dataflow_op(Tk2Op::CZ, [q0, q3])?;
-    let q0 = res.out_wire(0);
-    let q3 = res.out_wire(1);
-    let res = h.add_dataflow_op(Tk2Op::S, [q0])?;
-    let q0 = res.out_wire(0);
-    
-    h.finish_hugr_with_outputs([q0, q1, q2, q3], &PRELUDE_REGISTRY)
+    circuit!(&PRELUDE_REGISTRY; q0, q1, q2, q3 =>
+        H(q3); CZ(q2, q3); S(q3); H(q3); H(q0); S(q1); S(q2); CZ(q1, q2); S(q3);
+        H(q2); S(q2); H(q1); S(q0); CZ(q0, q3); S(q0)
+    )
+}
+
+/// Print the critical pairs of each stage that cannot be joined, which make
+/// the result depend on the order in which the rules are applied.
+fn report_critical_pairs(source: &str, stages: &[Stage], reg: &ExtensionRegistry) {
+    for stage in stages.iter() {
+        let pairs = critical_pairs(stage, reg);
+        let non_joinable: Vec<_> = pairs.iter().filter(|pair| !pair.is_joinable()).collect();
+        println!("Stage {} from {source}: {} critical pairs, {} not joinable", stage.name, pairs.len(), non_joinable.len());
+        for pair in non_joinable {
+            let (a, b) = pair.rules;
+            println!("  {} / {}", stage.rules[a].name, stage.rules[b].name);
+        }
+    }
+}
+
+/// Print the rules of each stage that cannot be shown to terminate, or the
+/// weights that show it if some were needed.
+fn report_termination(source: &str, stages: &[Stage]) {
+    for stage in stages.iter() {
+        let termination = check_termination(stage);
+        if let Some(weights) = &termination.weights {
+            let weights: Vec<String> = weights.iter().map(|(op, w)| format!("{op}={w}")).collect();
+            println!("Stage {} from {source} decreases weights {}", stage.name, weights.join(" "));
+        }
+        if !termination.is_proved() {
+            let flagged: Vec<&str> = termination.flagged.iter().map(|i| stage.rules[*i].name.as_str()).collect();
+            println!("Stage {} from {source} may not terminate: {}", stage.name, flagged.join(", "));
+        }
+    }
+}
+
+/// The value of the environment variable `name`, if it is set to a valid
+/// value. An invalid value is reported and ignored.
+fn env_var<T: FromStr>(name: &str) -> Option<T>
+where
+    T::Err: Display,
+{
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            eprintln!("Ignoring {name}={value}: {e}");
+            None
+        }
+    }
 }
 
 fn main() {
@@ -73,25 +107,131 @@ fn main() {
     let mut reg = PRELUDE_REGISTRY.clone();
     load_extensions_file(file, &mut reg).unwrap();
 
+    // Build the rule sets of the rewrite passes, or load them from the cache file if it is up to date
+    let mut rules = match std::env::var("MBQC_RULES_CACHE") {
+        Ok(cache) => MbqcRules::load(&cache).unwrap_or_else(|_| {
+            let rules = MbqcRules::new(&reg);
+            if let Err(e) = rules.save(&cache) {
+                eprintln!("Cannot save the rule sets to {cache}: {e}");
+            }
+            rules
+        }),
+        Err(_) => MbqcRules::new(&reg),
+    };
+    // Choose how the rewrites of each round are picked among overlapping matches
+    if let Some(selection) = env_var("MBQC_SELECTION") {
+        rules.config.selection = selection;
+    }
+    // Find and convert matches on as many threads as requested
+    if let Some(threads) = env_var("MBQC_THREADS") {
+        match rules.config.clone().with_threads(threads) {
+            Ok(config) => rules.config = config,
+            Err(e) => eprintln!("Ignoring MBQC_THREADS={threads}: {e}"),
+        }
+    }
+    // Check whether the result of each pass depends on the order of the rewrites
+    let check_confluence = std::env::var("MBQC_CHECK_CONFLUENCE").is_ok();
+    if check_confluence {
+        for (pass, stages) in pass_stages(&reg) {
+            report_critical_pairs(pass, &stages, &reg);
+        }
+    }
+    // Check that each pass terminates, beyond the cycle detection done while rewriting
+    let prove_termination = std::env::var("MBQC_CHECK_TERMINATION").is_ok();
+    if prove_termination {
+        for (pass, stages) in pass_stages(&reg) {
+            report_termination(pass, &stages);
+        }
+    }
+
     let mut circ = circ_example().unwrap();
     // viz_hugr(&circ);
 
+    // Step 0: Rewrite CX gates in terms of H and CZ gates, cancelling adjacent pairs of H gates
+    cx_to_cz(&mut circ, &rules).unwrap();
+
+    // Reduce the number of H gates, as each of them becomes an ancilla in step 1
+    let (before, after) = reduce_hadamards(&mut circ, &rules).unwrap();
+    println!("Reduced the number of H gates from {before} to {after}");
+
+    // Optionally, absorb Pauli gates into the correction frame, so that step 2 moves them to the outputs
+    absorb_paulis(&mut circ, &rules).unwrap();
+
     // Step 1: Convert each H gate to MBQC pattern
-    to_mbqc(&mut circ, &reg);
+    to_mbqc(&mut circ, &rules).unwrap();
 
     // Step 2: Push all corrections and S gates to the end of the qubit wire
-    push_corrections_and_s_gates(&mut circ, &reg);
+    push_corrections_and_s_gates(&mut circ, &rules).unwrap();
     // viz_hugr(&circ);
 
     // Step 3: Remove all corrections from ancilla qubits, propagating them to the boolean expression for the correction on output qubits
-    propagate_corrections(&mut circ, &reg);
+    propagate_corrections(&mut circ, &rules).unwrap();
+
+    // Apply any extra rules from the rule file given on the command line,
+    // recording the rewrites in the trace file given as second argument
+    if let Some(path) = std::env::args().nth(1) {
+        let stages = load_stages(&path, &reg).unwrap_or_else(|e| panic!("{path}: {e}"));
+        for stage in stages.iter() {
+            let names: Vec<&str> = stage.rules.iter().map(|rule| rule.name.as_str()).collect();
+            println!("Applying stage {} from {path}: {}", stage.name, names.join(", "));
+        }
+        if check_confluence {
+            report_critical_pairs(&path, &stages, &reg);
+        }
+        if prove_termination {
+            report_termination(&path, &stages);
+        }
+        let names: Vec<String> = stages.iter().flat_map(|stage| stage.rules.iter().map(|rule| rule.name.clone())).collect();
+        let stages: Vec<RuleSet> = stages.into_iter().map(RuleSet::from).collect();
+        let mut trace = RewriteTrace::new(names.clone());
+        if let Err(e) = apply_stages_with_config(&stages, &mut circ, &rules.config, Some(&mut trace)) {
+            // Report the offending rules by name rather than by index
+            let ids = match &e {
+                RewriteError::RoundLimit { rules, .. } | RewriteError::Cycle { rules, .. } => rules,
+            };
+            let offending: Vec<&str> = ids.iter().map(|i| names[*i].as_str()).collect();
+            panic!("{path}: {e} ({})", offending.join(", "));
+        }
+        if let Some(trace_path) = std::env::args().nth(2) {
+            trace.save(&trace_path).unwrap();
+            println!("Wrote {} rewrites to {trace_path}", trace.steps.len());
+        }
+    }
+
+    // Optionally, search for a cheaper pattern by applying the rules in any order, in both directions
+    let timeout = env_var("MBQC_SEARCH_SECONDS").and_then(|seconds: f64| {
+        Duration::try_from_secs_f64(seconds).map_err(|e| eprintln!("Ignoring MBQC_SEARCH_SECONDS={seconds}: {e}")).ok()
+    });
+    if let Some(timeout) = timeout {
+        let search_stages: Vec<Stage> = pass_stages(&reg)
+            .into_iter()
+            .filter(|(pass, _)| ["push_corrections_and_s_gates", "propagate_corrections"].contains(pass))
+            .flat_map(|(_, stages)| stages)
+            .collect();
+        let mut commutations: Vec<(String, String)> = search_stages.iter().flat_map(|stage| stage.commutations.clone()).collect();
+        commutations.sort();
+        commutations.dedup();
+        let search_rules = search_stages.into_iter().flat_map(|stage| stage.rules).collect();
+        let config = SearchConfig { timeout, ..SearchConfig::default() };
+        let before = MbqcCost::of(&circ);
+        circ = optimise(&circ, search_rules, commutations, &config);
+        println!("Search reduced the cost from {before} to {}", MbqcCost::of(&circ));
+    }
+
+    // Step 3b: Remove chains of Pauli-measured ancillas that act as the identity
+    let removed = remove_pauli_measurements(&mut circ, &rules, &reg).unwrap();
+    println!("Removed {removed} Pauli-measured qubits from the pattern");
     viz_hugr(&circ);
+    // Print the pattern as a sequence of measurement calculus commands
+    let pattern = Pattern::from_hugr(&circ).unwrap();
+    println!("{pattern}");
+    println!("Measurement depth: {}", pattern.signal_shift().measurement_depth());
 
     // Step 4: Convert the MBQC pattern to a circuit using n qubits
 
     // Step 5: Apply some basic depth reduction strategies
 
     // Step 6: Replace each operation from the ExtMBQC extension with its implementation in terms of Tk2Ops
-    prep_to_alloc(&mut circ, &reg);
+    prep_to_alloc(&mut circ, &rules).unwrap();
     // viz_hugr(&circ);
 }
@@ -0,0 +1,908 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::{FromStr, SplitWhitespace};
+
+use hugr::{
+    builder::{DFGBuilder, Dataflow, DataflowHugr},
+    extension::{prelude::QB_T, ExtensionRegistry},
+    ops::OpType,
+    types::FunctionType,
+    Hugr, HugrView, Node, OutgoingPort, Wire,
+};
+use tket2::Tk2Op;
+
+use crate::flow::OpenGraph;
+use crate::utils::{op_name, topological_order};
+
+/// A set of measurement outcomes, identified by the qubit that was measured.
+///
+/// A command conditioned on a domain is applied if and only if the XOR of the
+/// outcomes in the domain is true.
+pub type Domain = BTreeSet<usize>;
+
+/// A command of the measurement calculus (Danos, Kashefi, Panangaden 2007).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Prepare qubit `i` in the |+> state.
+    N(usize),
+    /// Entangle qubits `i` and `j` via a `CZ` gate.
+    E(usize, usize),
+    /// Measure qubit `qubit` in the XY plane of the Bloch sphere at `angle`
+    /// (given in multiples of π). The angle is negated if the XOR of the
+    /// `s_domain` is true and shifted by π if the XOR of the `t_domain` is true.
+    M { qubit: usize, angle: f64, s_domain: Domain, t_domain: Domain },
+    /// Apply a Pauli X on qubit `i` if the XOR of the domain is true.
+    X(usize, Domain),
+    /// Apply a Pauli Z on qubit `i` if the XOR of the domain is true.
+    Z(usize, Domain),
+    /// Apply the single-qubit gate `name` from `Tk2Op` on qubit `i`.
+    C(usize, String),
+}
+
+/// An MBQC pattern as a sequence of measurement calculus commands.
+///
+/// Commands are listed in the order they are applied. Qubits are identified
+/// by an index, and the outcome of the measurement of qubit `i` is referred
+/// to as `i` in the domains of the commands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub inputs: Vec<usize>,
+    pub outputs: Vec<usize>,
+    pub commands: Vec<Command>,
+}
+
+/// An error found while converting between HUGRs and patterns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternError {
+    /// The HUGR contains an operation with no measurement calculus counterpart.
+    UnsupportedOp(String),
+    /// The measurement angle (in multiples of π) is not a multiple of π/4.
+    UnsupportedAngle(f64),
+    /// A line of a command sequence could not be parsed.
+    Parse { line: usize, message: String },
+    /// The command at the given position acts on a qubit that is not available,
+    /// either because it was not prepared yet or because it was already measured.
+    InvalidQubit { command: usize, qubit: usize },
+    /// The command at the given position depends on the outcome of a qubit that
+    /// has not been measured yet.
+    InvalidSignal { command: usize, signal: usize },
+    /// The outputs of the pattern are not the qubits that were left unmeasured.
+    InvalidOutputs,
+    /// The command at the given position cannot be moved past a gate applied
+    /// earlier on the same qubit, so the pattern cannot be standardised.
+    CannotStandardise { command: usize },
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternError::UnsupportedOp(name) => {
+                write!(f, "operation {name} has no measurement calculus counterpart")
+            }
+            PatternError::UnsupportedAngle(angle) => {
+                write!(f, "measurement angle {angle}π is not a multiple of π/4")
+            }
+            PatternError::Parse { line, message } => write!(f, "line {line}: {message}"),
+            PatternError::InvalidQubit { command, qubit } => {
+                write!(f, "command {command} acts on qubit {qubit}, which is not available")
+            }
+            PatternError::InvalidSignal { command, signal } => write!(
+                f,
+                "command {command} depends on the outcome of qubit {signal}, which has not been measured yet"
+            ),
+            PatternError::InvalidOutputs => {
+                write!(f, "the outputs are not the qubits left unmeasured")
+            }
+            PatternError::CannotStandardise { command } => write!(
+                f,
+                "command {command} cannot be moved past a gate applied earlier on the same qubit"
+            ),
+        }
+    }
+}
+
+/// The single-qubit gates from `Tk2Op` that may appear in a `C` command.
+pub fn single_qubit_gate(name: &str) -> Option<Tk2Op> {
+    match name {
+        "H" => Some(Tk2Op::H),
+        "S" => Some(Tk2Op::S),
+        "Sdg" => Some(Tk2Op::Sdg),
+        "T" => Some(Tk2Op::T),
+        "Tdg" => Some(Tk2Op::Tdg),
+        "X" => Some(Tk2Op::X),
+        "Y" => Some(Tk2Op::Y),
+        "Z" => Some(Tk2Op::Z),
+        _ => None,
+    }
+}
+
+/// The angle (in multiples of π) of the single-qubit gates that are diagonal
+/// in the computational basis, i.e. the gates that are rotations around Z.
+pub fn diagonal_gate_angle(name: &str) -> Option<f64> {
+    match name {
+        "Z" => Some(1.0),
+        "S" => Some(0.5),
+        "Sdg" => Some(-0.5),
+        "T" => Some(0.25),
+        "Tdg" => Some(-0.25),
+        _ => None,
+    }
+}
+
+impl Pattern {
+    /// Convert a HUGR built out of `ExtMBQC` operations and `Tk2Op` gates into
+    /// a sequence of measurement calculus commands.
+    ///
+    /// Qubits are numbered as in `OpenGraph::from_hugr`. The domain of each
+    /// correction is obtained by flattening the tree of `XOR` and `Copy` nodes
+    /// that produces its signal. Corrections and diagonal gates that are
+    /// applied right before a measurement are absorbed into the measurement's
+    /// domains and angle. Any other single-qubit gate becomes a `C` command,
+    /// and so does the constant part of a correction whose signal involves a
+    /// `ConstTrue` node.
+    pub fn from_hugr(circ: &Hugr) -> Result<Self, PatternError> {
+        let graph = OpenGraph::from_hugr(circ);
+
+        // The qubit each of the nodes acts on, for each of its qubit ports
+        let mut qubits: HashMap<Node, Vec<usize>> = HashMap::new();
+        let mut measured_by: HashMap<Node, usize> = HashMap::new();
+        for (v, vertex) in graph.vertices.iter().enumerate() {
+            for (node, port) in vertex.path.iter() {
+                let ports = qubits.entry(*node).or_default();
+                ports.resize(ports.len().max(port + 1), 0);
+                ports[*port] = v;
+            }
+            if vertex.is_measured(circ) {
+                measured_by.insert(vertex.end.0, v);
+            }
+        }
+
+        // Absorb the operations right before each measurement into the measurement itself
+        let mut measurements = HashMap::new();
+        let mut absorbed = BTreeSet::new();
+        for &v in graph.measured.iter() {
+            let mut angle = 0.0;
+            let mut s_domain = Domain::new();
+            let mut t_domain = Domain::new();
+            let mut negated = false;
+            for (node, _) in graph.vertices[v].path.iter().rev() {
+                let name = op_name(circ, *node);
+                match (name.as_str(), diagonal_gate_angle(&name)) {
+                    ("CorrectionX", _) => {
+                        let (domain, constant) = signal_domain(circ, *node, &measured_by)?;
+                        s_domain = xor(&s_domain, &domain);
+                        // An X gate right before the measurement negates its angle
+                        if constant {
+                            angle = -angle;
+                            negated = true;
+                        }
+                    }
+                    ("CorrectionZ", _) => {
+                        let (domain, constant) = signal_domain(circ, *node, &measured_by)?;
+                        t_domain = xor(&t_domain, &domain);
+                        // A Z gate right before the measurement shifts its angle by π
+                        if constant {
+                            angle += 1.0;
+                        }
+                    }
+                    // A diagonal gate only commutes with the Z corrections
+                    (_, Some(phi)) if s_domain.is_empty() && !negated => angle -= phi,
+                    _ => break,
+                }
+                absorbed.insert(*node);
+            }
+            let angle = f64::rem_euclid(angle, 2.0);
+            let command = Command::M { qubit: v, angle, s_domain, t_domain };
+            measurements.insert(graph.vertices[v].end.0, command);
+        }
+
+        // Translate each node into a command, in topological order
+        let mut commands = vec![];
+        for node in topological_order(circ) {
+            if absorbed.contains(&node) {
+                continue;
+            }
+            let name = op_name(circ, node);
+            match name.as_str() {
+                "Input" | "Output" | "XOR" | "Copy" | "DiscardSignal" | "ConstFalse" | "ConstTrue" => (),
+                "PrepPlus" => {
+                    let v = graph.vertices.iter().position(|vertex| vertex.start.0 == node);
+                    commands.push(Command::N(v.unwrap()));
+                }
+                "CZ" => commands.push(Command::E(qubits[&node][0], qubits[&node][1])),
+                "MeasureX" => commands.push(measurements.remove(&node).unwrap()),
+                "CorrectionX" | "CorrectionZ" => {
+                    let (domain, constant) = signal_domain(circ, node, &measured_by)?;
+                    let qubit = qubits[&node][0];
+                    // A correction with an empty domain is never applied
+                    if !domain.is_empty() {
+                        commands.push(match name.as_str() {
+                            "CorrectionX" => Command::X(qubit, domain),
+                            _ => Command::Z(qubit, domain),
+                        });
+                    }
+                    // The constant part of the signal is always applied, as a Pauli gate
+                    if constant {
+                        let pauli = &name["Correction".len()..];
+                        commands.push(Command::C(qubit, pauli.to_string()));
+                    }
+                }
+                _ if single_qubit_gate(&name).is_some() => {
+                    commands.push(Command::C(qubits[&node][0], name))
+                }
+                _ => return Err(PatternError::UnsupportedOp(name)),
+            }
+        }
+
+        // The outputs are listed in the order of the ports of the `Output` node
+        let mut outputs: Vec<usize> = graph.outputs.iter().copied().collect();
+        outputs.sort_by_key(|v| graph.vertices[*v].end.1.index());
+
+        Ok(Pattern {
+            inputs: graph.inputs.iter().copied().collect(),
+            outputs,
+            commands,
+        })
+    }
+
+    /// Check that the pattern is well formed.
+    ///
+    /// Each command must act on qubits that are either inputs or have been
+    /// prepared, and that have not been measured yet. The domain of each
+    /// command may only refer to qubits measured by an earlier command. Every
+    /// qubit left unmeasured must be an output.
+    pub fn validate(&self) -> Result<(), PatternError> {
+        let mut available: BTreeSet<usize> = self.inputs.iter().copied().collect();
+        let mut introduced = available.clone();
+        let mut measured = BTreeSet::new();
+        for (c, command) in self.commands.iter().enumerate() {
+            let qubits = match command {
+                Command::N(i) => {
+                    if !introduced.insert(*i) {
+                        return Err(PatternError::InvalidQubit { command: c, qubit: *i });
+                    }
+                    available.insert(*i);
+                    continue;
+                }
+                Command::E(i, j) if i == j => {
+                    return Err(PatternError::InvalidQubit { command: c, qubit: *i })
+                }
+                Command::E(i, j) => vec![*i, *j],
+                Command::M { qubit, .. } => vec![*qubit],
+                Command::X(i, _) | Command::Z(i, _) => vec![*i],
+                Command::C(_, name) if single_qubit_gate(name).is_none() => {
+                    return Err(PatternError::UnsupportedOp(name.clone()))
+                }
+                Command::C(i, _) => vec![*i],
+            };
+            if let Some(q) = qubits.iter().find(|q| !available.contains(q)) {
+                return Err(PatternError::InvalidQubit { command: c, qubit: *q });
+            }
+            let mut signals = command.domains().into_iter().flatten();
+            if let Some(s) = signals.find(|s| !measured.contains(*s)) {
+                return Err(PatternError::InvalidSignal { command: c, signal: *s });
+            }
+            if let Command::M { qubit, .. } = command {
+                available.remove(qubit);
+                measured.insert(*qubit);
+            }
+        }
+
+        let outputs: BTreeSet<usize> = self.outputs.iter().copied().collect();
+        if outputs != available || outputs.len() != self.outputs.len() {
+            return Err(PatternError::InvalidOutputs);
+        }
+        Ok(())
+    }
+
+    /// Build a HUGR implementing the pattern using `ExtMBQC` operations.
+    ///
+    /// Each outcome is copied via `Copy` nodes as many times as it is used and
+    /// each domain is computed with a chain of `XOR` nodes. Outcomes that are
+    /// never used are discarded via `DiscardSignal`. Measurement domains become
+    /// corrections right before the measurement, and measurement angles become
+    /// diagonal gates from `Tk2Op`, so only multiples of π/4 are supported.
+    pub fn to_hugr(&self, registry: &ExtensionRegistry) -> Result<Hugr, PatternError> {
+        self.validate()?;
+
+        // Load the extension
+        let extension = registry.get("ExtMBQC").unwrap();
+        let prepare_op = extension.instantiate_extension_op("PrepPlus", [], registry).unwrap();
+        let measure_op = extension.instantiate_extension_op("MeasureX", [], registry).unwrap();
+        let x_corr = extension.instantiate_extension_op("CorrectionX", [], registry).unwrap();
+        let z_corr = extension.instantiate_extension_op("CorrectionZ", [], registry).unwrap();
+        let copy = extension.instantiate_extension_op("Copy", [], registry).unwrap();
+        let xor_op = extension.instantiate_extension_op("XOR", [], registry).unwrap();
+        let discard = extension.instantiate_extension_op("DiscardSignal", [], registry).unwrap();
+
+        // Count how many times each of the outcomes is used
+        let mut uses: HashMap<usize, usize> = HashMap::new();
+        for command in self.commands.iter() {
+            for domain in command.domains() {
+                for s in domain.iter() {
+                    *uses.entry(*s).or_default() += 1;
+                }
+            }
+        }
+
+        // Build the HUGR
+        let signature = FunctionType::new(vec![QB_T; self.inputs.len()], vec![QB_T; self.outputs.len()]);
+        let mut h = DFGBuilder::new(signature).unwrap();
+        let mut qubits: HashMap<usize, Wire> = self.inputs.iter().copied().zip(h.input_wires()).collect();
+        let mut signals: HashMap<usize, Vec<Wire>> = HashMap::new();
+
+        for command in self.commands.iter() {
+            match command {
+                Command::N(i) => {
+                    let res = h.add_dataflow_op(prepare_op.clone(), []).unwrap();
+                    qubits.insert(*i, res.out_wire(0));
+                }
+                Command::E(i, j) => {
+                    let res = h.add_dataflow_op(Tk2Op::CZ, [qubits[i], qubits[j]]).unwrap();
+                    qubits.insert(*i, res.out_wire(0));
+                    qubits.insert(*j, res.out_wire(1));
+                }
+                Command::M { qubit, angle, s_domain, t_domain } => {
+                    // The measurement is equivalent to applying Z^t, then X^s, then
+                    // a rotation by -angle around Z and finally measuring in the X basis
+                    let mut q = qubits.remove(qubit).unwrap();
+                    for (corr, domain) in [(&z_corr, t_domain), (&x_corr, s_domain)] {
+                        if !domain.is_empty() {
+                            let c = domain_signal(&mut h, &mut signals, domain, &xor_op);
+                            q = h.add_dataflow_op(corr.clone(), [q, c]).unwrap().out_wire(0);
+                        }
+                    }
+                    let gates = rotation_gates(-angle).ok_or(PatternError::UnsupportedAngle(*angle))?;
+                    for gate in gates {
+                        q = h.add_dataflow_op(gate, [q]).unwrap().out_wire(0);
+                    }
+                    let mut c = h.add_dataflow_op(measure_op.clone(), [q]).unwrap().out_wire(0);
+
+                    // Make a copy of the outcome for each time it is used
+                    let n_uses = uses.get(qubit).copied().unwrap_or(0);
+                    if n_uses == 0 {
+                        h.add_dataflow_op(discard.clone(), [c]).unwrap();
+                        continue;
+                    }
+                    let mut copies = vec![];
+                    for _ in 1..n_uses {
+                        let res = h.add_dataflow_op(copy.clone(), [c]).unwrap();
+                        copies.push(res.out_wire(0));
+                        c = res.out_wire(1);
+                    }
+                    copies.push(c);
+                    signals.insert(*qubit, copies);
+                }
+                Command::X(i, domain) | Command::Z(i, domain) => {
+                    if domain.is_empty() {
+                        continue;
+                    }
+                    let corr = match command {
+                        Command::X(..) => &x_corr,
+                        _ => &z_corr,
+                    };
+                    let c = domain_signal(&mut h, &mut signals, domain, &xor_op);
+                    let res = h.add_dataflow_op(corr.clone(), [qubits[i], c]).unwrap();
+                    qubits.insert(*i, res.out_wire(0));
+                }
+                Command::C(i, name) => {
+                    let gate = single_qubit_gate(name).unwrap();
+                    let res = h.add_dataflow_op(gate, [qubits[i]]).unwrap();
+                    qubits.insert(*i, res.out_wire(0));
+                }
+            }
+        }
+
+        let outputs: Vec<Wire> = self.outputs.iter().map(|o| qubits[o]).collect();
+        Ok(h.finish_hugr_with_outputs(outputs, registry).unwrap())
+    }
+}
+
+/// The Pauli corrections pending on a qubit, as the domains of an X and a Z
+/// correction to be applied on it.
+#[derive(Debug, Clone, Default)]
+struct Frame {
+    x: Domain,
+    z: Domain,
+}
+
+/// The Pauli `U† P U` for a Pauli `P` given as `(has_x, has_z)`, up to phase.
+/// Returns `None` if the gate `U` is not a Clifford.
+fn conjugate_pauli(gate: &str, (x, z): (bool, bool)) -> Option<(bool, bool)> {
+    match gate {
+        "H" => Some((z, x)),
+        "S" | "Sdg" => Some((x, z ^ x)),
+        "X" | "Y" | "Z" => Some((x, z)),
+        _ => None,
+    }
+}
+
+impl Pattern {
+    /// Put the pattern into standard form, i.e. all preparations first, then
+    /// all entangling commands, then all measurements and finally all
+    /// corrections.
+    ///
+    /// Commands are moved using the standard commutation rules of the
+    /// measurement calculus: `E_ij X_i^s = X_i^s Z_j^s E_ij`, `E_ij Z_i^s = Z_i^s E_ij`
+    /// and corrections right before a measurement are absorbed into its
+    /// domains. Local gates (`C` commands) are kept after the corrections on
+    /// their qubit, conjugating any later correction through them. The pattern
+    /// cannot be standardised if a qubit with a local gate is later entangled or
+    /// measured, or if a later correction cannot be conjugated through it.
+    pub fn standardise(&self) -> Result<Pattern, PatternError> {
+        self.validate()?;
+
+        let mut preparations = vec![];
+        let mut entanglers = vec![];
+        let mut measurements = vec![];
+        // The corrections and local gates accumulated so far on each qubit
+        let mut frames: HashMap<usize, Frame> = HashMap::new();
+        let mut local_gates: HashMap<usize, Vec<String>> = HashMap::new();
+
+        for (c, command) in self.commands.iter().enumerate() {
+            let has_local_gates = |q: &usize| local_gates.get(q).is_some_and(|g| !g.is_empty());
+            match command {
+                Command::N(_) => preparations.push(command.clone()),
+                Command::E(i, j) => {
+                    if has_local_gates(i) || has_local_gates(j) {
+                        return Err(PatternError::CannotStandardise { command: c });
+                    }
+                    // An X correction on one end of the CZ becomes a Z correction on the other
+                    let x_i = frames.entry(*i).or_default().x.clone();
+                    let x_j = frames.entry(*j).or_default().x.clone();
+                    let frame_i = frames.get_mut(i).unwrap();
+                    frame_i.z = xor(&frame_i.z, &x_j);
+                    let frame_j = frames.get_mut(j).unwrap();
+                    frame_j.z = xor(&frame_j.z, &x_i);
+                    entanglers.push(command.clone());
+                }
+                Command::M { qubit, angle, s_domain, t_domain } => {
+                    if has_local_gates(qubit) {
+                        return Err(PatternError::CannotStandardise { command: c });
+                    }
+                    let frame = frames.remove(qubit).unwrap_or_default();
+                    measurements.push(Command::M {
+                        qubit: *qubit,
+                        angle: *angle,
+                        s_domain: xor(s_domain, &frame.x),
+                        t_domain: xor(t_domain, &frame.z),
+                    });
+                }
+                Command::X(i, domain) | Command::Z(i, domain) => {
+                    // Move the correction before the local gates applied on the qubit
+                    let mut pauli = (matches!(command, Command::X(..)), matches!(command, Command::Z(..)));
+                    for gate in local_gates.get(i).into_iter().flatten().rev() {
+                        pauli = conjugate_pauli(gate, pauli)
+                            .ok_or(PatternError::CannotStandardise { command: c })?;
+                    }
+                    let frame = frames.entry(*i).or_default();
+                    if pauli.0 {
+                        frame.x = xor(&frame.x, domain);
+                    }
+                    if pauli.1 {
+                        frame.z = xor(&frame.z, domain);
+                    }
+                }
+                Command::C(i, name) => local_gates.entry(*i).or_default().push(name.clone()),
+            }
+        }
+
+        // Corrections are applied on the outputs, followed by their local gates
+        let mut commands = preparations;
+        commands.append(&mut entanglers);
+        commands.append(&mut measurements);
+        for o in self.outputs.iter() {
+            let frame = frames.remove(o).unwrap_or_default();
+            if !frame.x.is_empty() {
+                commands.push(Command::X(*o, frame.x));
+            }
+            if !frame.z.is_empty() {
+                commands.push(Command::Z(*o, frame.z));
+            }
+            for gate in local_gates.remove(o).unwrap_or_default() {
+                commands.push(Command::C(*o, gate));
+            }
+        }
+
+        Ok(Pattern {
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            commands,
+        })
+    }
+
+    /// Remove the t-domains of all measurements via signal shifting.
+    ///
+    /// A measurement with a t-domain `t` is equivalent to the same measurement
+    /// without it, followed by the substitution of its outcome `s_i` by
+    /// `s_i + t` in every later command (Danos, Kashefi, Panangaden 2007). The
+    /// dependency on `t` is thus moved onto later commands, and ultimately onto
+    /// the corrections on the outputs.
+    ///
+    /// Before shifting, the s-domain of Pauli measurements is simplified too:
+    /// negating the angle of an X measurement has no effect, so its s-domain is
+    /// dropped; negating the angle of a Y measurement is the same as shifting
+    /// it by π, so its s-domain is moved into its t-domain.
+    pub fn signal_shift(&self) -> Pattern {
+        // The domain that replaces each of the outcomes that has been shifted
+        let mut shifts: HashMap<usize, Domain> = HashMap::new();
+        let expand = |domain: &Domain, shifts: &HashMap<usize, Domain>| {
+            domain.iter().fold(Domain::new(), |acc, s| {
+                xor(&acc, shifts.get(s).unwrap_or(&Domain::from([*s])))
+            })
+        };
+
+        let mut commands = vec![];
+        for command in self.commands.iter() {
+            commands.push(match command {
+                Command::M { qubit, angle, s_domain, t_domain } => {
+                    let mut s_domain = expand(s_domain, &shifts);
+                    let mut t_domain = expand(t_domain, &shifts);
+                    if same_angle(*angle, 0.0) || same_angle(*angle, 1.0) {
+                        s_domain.clear();
+                    } else if same_angle(*angle, 0.5) || same_angle(*angle, 1.5) {
+                        t_domain = xor(&t_domain, &s_domain);
+                        s_domain.clear();
+                    }
+                    if !t_domain.is_empty() {
+                        shifts.insert(*qubit, xor(&Domain::from([*qubit]), &t_domain));
+                    }
+                    Command::M { qubit: *qubit, angle: *angle, s_domain, t_domain: Domain::new() }
+                }
+                Command::X(i, domain) => Command::X(*i, expand(domain, &shifts)),
+                Command::Z(i, domain) => Command::Z(*i, expand(domain, &shifts)),
+                _ => command.clone(),
+            });
+        }
+
+        Pattern {
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            commands,
+        }
+    }
+
+    /// The length of the longest chain of measurements where each measurement
+    /// depends on the outcome of the previous one.
+    ///
+    /// Measurements that do not depend on any outcome have depth one, so this
+    /// is the number of rounds of measurements needed to run the pattern.
+    pub fn measurement_depth(&self) -> usize {
+        let mut depths: HashMap<usize, usize> = HashMap::new();
+        for command in self.commands.iter() {
+            if let Command::M { qubit, .. } = command {
+                let signals = command.domains().into_iter().flatten();
+                let depth = 1 + signals.map(|s| depths[s]).max().unwrap_or(0);
+                depths.insert(*qubit, depth);
+            }
+        }
+        depths.into_values().max().unwrap_or(0)
+    }
+}
+
+impl Command {
+    /// The domains the command depends on.
+    pub fn domains(&self) -> Vec<&Domain> {
+        match self {
+            Command::M { s_domain, t_domain, .. } => vec![s_domain, t_domain],
+            Command::X(_, domain) | Command::Z(_, domain) => vec![domain],
+            _ => vec![],
+        }
+    }
+}
+
+/// Whether two angles (in multiples of π) are equal modulo 2π, up to rounding
+/// errors.
+fn same_angle(a: f64, b: f64) -> bool {
+    let diff = f64::rem_euclid(a - b, 2.0);
+    diff < 1e-9 || diff > 2.0 - 1e-9
+}
+
+/// The diagonal gates implementing a rotation by `angle` (in multiples of π)
+/// around Z, up to global phase. Returns `None` if the angle is not a
+/// multiple of π/4.
+fn rotation_gates(angle: f64) -> Option<Vec<Tk2Op>> {
+    let quarters = angle * 4.0;
+    if (quarters - quarters.round()).abs() > 1e-9 {
+        return None;
+    }
+    let k = (quarters.round() as i64).rem_euclid(8);
+    let mut gates = vec![];
+    if k & 4 != 0 {
+        gates.push(Tk2Op::Z);
+    }
+    if k & 2 != 0 {
+        gates.push(Tk2Op::S);
+    }
+    if k & 1 != 0 {
+        gates.push(Tk2Op::T);
+    }
+    Some(gates)
+}
+
+/// Compute the XOR of the outcomes in a non-empty domain, consuming one of
+/// the copies of each outcome.
+fn domain_signal(
+    h: &mut DFGBuilder<Hugr>,
+    signals: &mut HashMap<usize, Vec<Wire>>,
+    domain: &Domain,
+    xor_op: &(impl Into<OpType> + Clone),
+) -> Wire {
+    let mut outcomes = domain.iter().map(|s| signals.get_mut(s).unwrap().pop().unwrap());
+    let first = outcomes.next().unwrap();
+    let rest: Vec<Wire> = outcomes.collect();
+    rest.into_iter().fold(first, |acc, c| {
+        h.add_dataflow_op(xor_op.clone(), [acc, c]).unwrap().out_wire(0)
+    })
+}
+
+/// The symmetric difference of two domains.
+fn xor(a: &Domain, b: &Domain) -> Domain {
+    a.symmetric_difference(b).copied().collect()
+}
+
+/// The domain of the signal controlling the correction at `node`, together
+/// with its constant part.
+fn signal_domain(
+    circ: &Hugr,
+    node: Node,
+    measured_by: &HashMap<Node, usize>,
+) -> Result<(Domain, bool), PatternError> {
+    let (source, port) = circ.single_linked_output(node, 1).unwrap();
+    flatten_signal(circ, source, port, measured_by)
+}
+
+/// Flatten the tree of `XOR` and `Copy` nodes producing a signal into a domain.
+///
+/// Constant signals do not belong in a domain, so the XOR of all the constant
+/// signals in the tree is returned alongside it.
+fn flatten_signal(
+    circ: &Hugr,
+    node: Node,
+    port: OutgoingPort,
+    measured_by: &HashMap<Node, usize>,
+) -> Result<(Domain, bool), PatternError> {
+    let name = op_name(circ, node);
+    match name.as_str() {
+        "MeasureX" => Ok((Domain::from([measured_by[&node]]), false)),
+        "ConstFalse" => Ok((Domain::new(), false)),
+        "ConstTrue" => Ok((Domain::new(), true)),
+        "Copy" => {
+            let (source, source_port) = circ.single_linked_output(node, 0).unwrap();
+            flatten_signal(circ, source, source_port, measured_by)
+        }
+        "XOR" => {
+            let mut domain = Domain::new();
+            let mut constant = false;
+            for p in 0..2 {
+                let (source, source_port) = circ.single_linked_output(node, p).unwrap();
+                let (d, c) = flatten_signal(circ, source, source_port, measured_by)?;
+                domain = xor(&domain, &d);
+                constant ^= c;
+            }
+            Ok((domain, constant))
+        }
+        _ => Err(PatternError::UnsupportedOp(format!("{name} (port {})", port.index()))),
+    }
+}
+
+/// Write a domain as a list of space separated outcomes within square brackets.
+fn fmt_domain(domain: &Domain) -> String {
+    let outcomes: Vec<String> = domain.iter().map(|i| i.to_string()).collect();
+    format!("[{}]", outcomes.join(" "))
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::N(i) => write!(f, "N {i}"),
+            Command::E(i, j) => write!(f, "E {i} {j}"),
+            Command::M { qubit, angle, s_domain, t_domain } => write!(
+                f,
+                "M {qubit} {angle} {} {}",
+                fmt_domain(s_domain),
+                fmt_domain(t_domain)
+            ),
+            Command::X(i, domain) => write!(f, "X {i} {}", fmt_domain(domain)),
+            Command::Z(i, domain) => write!(f, "Z {i} {}", fmt_domain(domain)),
+            Command::C(i, name) => write!(f, "C {i} {name}"),
+        }
+    }
+}
+
+/// Patterns are written one command per line, preceded by a line `I ...` listing
+/// the input qubits and a line `O ...` listing the output qubits.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inputs: Vec<String> = self.inputs.iter().map(|i| i.to_string()).collect();
+        let outputs: Vec<String> = self.outputs.iter().map(|i| i.to_string()).collect();
+        writeln!(f, "I {}", inputs.join(" "))?;
+        writeln!(f, "O {}", outputs.join(" "))?;
+        for command in self.commands.iter() {
+            writeln!(f, "{command}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a qubit index.
+fn parse_index(tokens: &mut SplitWhitespace) -> Result<usize, String> {
+    let token = tokens.next().ok_or("missing qubit index")?;
+    token.parse().map_err(|_| format!("invalid qubit index {token}"))
+}
+
+/// Parse a domain such as `[0 3 4]`. A missing domain is an empty domain.
+fn parse_domain(tokens: &mut SplitWhitespace) -> Result<Domain, String> {
+    match tokens.next() {
+        None => Ok(Domain::new()),
+        Some("[") => {
+            let mut domain = Domain::new();
+            loop {
+                match tokens.next() {
+                    None => return Err("unterminated domain".to_string()),
+                    Some("]") => return Ok(domain),
+                    Some(token) => {
+                        let s = token.parse().map_err(|_| format!("invalid outcome {token}"))?;
+                        domain.insert(s);
+                    }
+                }
+            }
+        }
+        Some(token) => Err(format!("expected a domain, found {token}")),
+    }
+}
+
+/// Parse the arguments of a command of the given kind.
+fn parse_command(kind: &str, tokens: &mut SplitWhitespace) -> Result<Command, String> {
+    let command = match kind {
+        "N" => Command::N(parse_index(tokens)?),
+        "E" => Command::E(parse_index(tokens)?, parse_index(tokens)?),
+        "M" => {
+            let qubit = parse_index(tokens)?;
+            let token = tokens.next().ok_or("missing measurement angle")?;
+            let angle: f64 = token.parse().map_err(|_| format!("invalid angle {token}"))?;
+            let angle = f64::rem_euclid(angle, 2.0);
+            let s_domain = parse_domain(tokens)?;
+            let t_domain = parse_domain(tokens)?;
+            Command::M { qubit, angle, s_domain, t_domain }
+        }
+        "X" => Command::X(parse_index(tokens)?, parse_domain(tokens)?),
+        "Z" => Command::Z(parse_index(tokens)?, parse_domain(tokens)?),
+        "C" => {
+            let qubit = parse_index(tokens)?;
+            let name = tokens.next().ok_or("missing gate name")?;
+            Command::C(qubit, name.to_string())
+        }
+        _ => return Err(format!("unknown command {kind}")),
+    };
+    match tokens.next() {
+        None => Ok(command),
+        Some(token) => Err(format!("unexpected {token}")),
+    }
+}
+
+/// Parse a pattern written in the format produced by its `Display` implementation.
+///
+/// Everything after a `#` in a line is a comment. Measurement angles are
+/// taken modulo 2 (i.e. 2π), and measurement domains may be omitted when
+/// empty, e.g. the following is the pattern for `H`:
+/// ```text
+/// I 0
+/// O 1
+/// N 1
+/// E 0 1
+/// M 0 0
+/// X 1 [0]
+/// ```
+/// The resulting pattern is validated (see `Pattern::validate`).
+impl FromStr for Pattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut inputs = vec![];
+        let mut outputs = vec![];
+        let mut commands = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let parse_error = |message| PatternError::Parse { line: i + 1, message };
+            // Remove comments and make sure brackets are separate tokens
+            let line = line.split('#').next().unwrap();
+            let line = line.replace('[', " [ ").replace(']', " ] ");
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                None => continue,
+                Some("I") => inputs = tokens.map(|t| t.parse()).collect::<Result<_, _>>()
+                    .map_err(|_| parse_error("invalid input qubit".to_string()))?,
+                Some("O") => outputs = tokens.map(|t| t.parse()).collect::<Result<_, _>>()
+                    .map_err(|_| parse_error("invalid output qubit".to_string()))?,
+                Some(kind) => commands.push(parse_command(kind, &mut tokens).map_err(parse_error)?),
+            }
+        }
+
+        let pattern = Pattern { inputs, outputs, commands };
+        pattern.validate()?;
+        Ok(pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tket2::circuit::CircuitHash;
+
+    use super::*;
+    use crate::test_utils::registry;
+
+    #[test]
+    fn angles_are_compared_modulo_2() {
+        let pattern: Pattern = "I 0\nO 2\nN 1\nN 2\nE 0 1\nE 1 2\nM 0 -1.5\nM 1 2.000000000001 [0]"
+            .parse()
+            .unwrap();
+        let Command::M { angle, .. } = &pattern.commands[4] else {
+            panic!("expected a measurement");
+        };
+        assert_eq!(*angle, 0.5);
+        // The second measurement is an X measurement up to rounding, so its s-domain is dropped
+        let shifted = pattern.signal_shift();
+        let Command::M { s_domain, t_domain, .. } = &shifted.commands[5] else {
+            panic!("expected a measurement");
+        };
+        assert!(s_domain.is_empty() && t_domain.is_empty());
+    }
+
+    #[test]
+    fn display_pattern() {
+        let pattern: Pattern = "I 0\nO 1\nN 1 # the output\nE 0 1\nM 0 0.25\nX 1 [0]".parse().unwrap();
+        let written = pattern.to_string();
+        assert_eq!(written, "I 0\nO 1\nN 1\nE 0 1\nM 0 0.25 [] []\nX 1 [0]\n");
+        assert_eq!(written.parse::<Pattern>().unwrap(), pattern);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let unknown_qubit = "I 0\nO 0\nE 0 1".parse::<Pattern>();
+        assert_eq!(unknown_qubit, Err(PatternError::InvalidQubit { command: 0, qubit: 1 }));
+        let early_signal = "I 0\nO 1\nN 1\nE 0 1\nX 1 [0]\nM 0 0".parse::<Pattern>();
+        assert_eq!(early_signal, Err(PatternError::InvalidSignal { command: 2, signal: 0 }));
+        // Angles that are not multiples of π/4 can be parsed but not converted
+        let pattern: Pattern = "I 0\nO 1\nN 1\nE 0 1\nM 0 0.1\nX 1 [0]".parse().unwrap();
+        assert_eq!(pattern.to_hugr(&registry()).err(), Some(PatternError::UnsupportedAngle(0.1)));
+    }
+
+    #[test]
+    fn standardise_interleaved_corrections() {
+        let reg = registry();
+        // Two teleportations in a row, each followed by its correction
+        let pattern: Pattern = "I 0\nO 2\nN 1\nE 0 1\nM 0 0\nX 1 [0]\nN 2\nE 1 2\nM 1 0\nX 2 [1]"
+            .parse()
+            .unwrap();
+        let standard = pattern.standardise().unwrap();
+        let kind = |command: &Command| match command {
+            Command::N(_) => 0,
+            Command::E(..) => 1,
+            Command::M { .. } => 2,
+            _ => 3,
+        };
+        assert!(standard.commands.windows(2).all(|w| kind(&w[0]) <= kind(&w[1])));
+        // The X correction on 1 is absorbed into its measurement and becomes a Z correction on 2
+        let expected: Pattern = "I 0\nO 2\nN 1\nN 2\nE 0 1\nE 1 2\nM 0 0\nM 1 0 [0]\nX 2 [1]\nZ 2 [0]"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            standard.to_hugr(&reg).unwrap().circuit_hash(),
+            expected.to_hugr(&reg).unwrap().circuit_hash()
+        );
+    }
+
+    #[test]
+    fn signal_shift_reduces_depth() {
+        // Each measurement depends on the previous one through its t-domain
+        let pattern: Pattern = "I 0\nO 3\nN 1\nN 2\nN 3\nE 0 1\nE 1 2\nE 2 3\n\
+                                M 0 0.25\nM 1 0.25 [] [0]\nM 2 0.25 [0] [1]\nX 3 [2]"
+            .parse()
+            .unwrap();
+        assert_eq!(pattern.measurement_depth(), 3);
+        let shifted = pattern.signal_shift();
+        assert_eq!(shifted.measurement_depth(), 2);
+        // The t-domains are moved onto the correction of the output
+        assert_eq!(shifted.commands.last(), Some(&Command::X(3, Domain::from([0, 1, 2]))));
+    }
+}
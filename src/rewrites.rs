@@ -1,83 +1,227 @@
-use hugr::{Hugr, extension::ExtensionRegistry};
-
-use crate::patterns::*;
-use crate::utils::apply_rules_exhaustively;
-
-
-pub fn to_mbqc(circ: &mut Hugr, reg: &ExtensionRegistry) {
-    // Specify the rewrite rules
-    let rules = vec![
-        (h(), mbqc_h(reg)),
-    ]
-    // Unwrap all of the above `Result<Hugr, BuildError>` types into `Hugr`
-    .iter()
-    .map(|rule| (rule.0.clone().unwrap(), rule.1.clone().unwrap()))
-    .collect();
-    // Apply them exhaustively
-    apply_rules_exhaustively(rules, circ);
-}
-
-pub fn push_corrections_and_s_gates(circ: &mut Hugr, reg: &ExtensionRegistry) {
-    // Specify the rewrite rules
-    let rules = vec![
-        // Push corrections
-        (xcorr_h(&reg), h_zcorr(&reg)),
-        (zcorr_h(&reg), h_xcorr(&reg)),
-        (xicorr_cz(&reg), cz_xzcorr(&reg)),
-        (ixcorr_cz(&reg), cz_zxcorr(&reg)),
-        (zicorr_cz(&reg), cz_zicorr(&reg)),
-        (izcorr_cz(&reg), cz_izcorr(&reg)),
-        (xcorr_s(&reg), s_xcorr_zcorr(&reg)),
-        (zcorr_s(&reg), s_zcorr(&reg)),
-        // Push S gates
-        (s_cz_0(), cz_s_0()),
-        (s_cz_1(), cz_s_1()),
-    ]
-    // Unwrap all of the above `Result<Hugr, BuildError>` types into `Hugr`
-    .iter()
-    .map(|rule| (rule.0.clone().unwrap(), rule.1.clone().unwrap()))
-    .collect();
-    // Apply them exhaustively
-    apply_rules_exhaustively(rules, circ);
-}
-
-pub fn propagate_corrections(circ: &mut Hugr, reg: &ExtensionRegistry) {
-    // Specify the rewrite rules
-    let rules = vec![
-        // X corrections before an X measurement only contribute to a global phase, so we remove them
-        (xcorr_xmeas(&reg), xmeas_discard_input_signal(&reg)),
-        // Z corrections before an X measurement can be propagated to the classical signal
-        (zcorr_xmeas(&reg), xmeas_xor(&reg)),
-        // Since the first rule introduced `DiscardSignal` nodes, we may remove some `Copy` and `XOR` nodes
-        (copy_discard_0(&reg), classical_wire(&reg)),
-        (copy_discard_1(&reg), classical_wire(&reg)),
-        (xor_discard(&reg), discard_both(&reg)),
-        // Merge Z corrections together
-        (xcorr_xcorr(&reg), xor_xcorr(&reg)),
-        // Merge X corrections together
-        (zcorr_zcorr(&reg), xor_zcorr(&reg)),
-        // Make sure that Z corrections appear after X corrections, so that merging can be maximised
-        (zcorr_xcorr(&reg), xcorr_zcorr(&reg)),
-    ]
-    // Unwrap all of the above `Result<Hugr, BuildError>` types into `Hugr`
-    .iter()
-    .map(|rule| (rule.0.clone().unwrap(), rule.1.clone().unwrap()))
-    .collect();
-    // Apply them exhaustively
-    apply_rules_exhaustively(rules, circ);
-}
-
-
-
-pub fn prep_to_alloc(circ: &mut Hugr, reg: &ExtensionRegistry) {
-    // Specify the rewrite rules
-    let rules = vec![
-        (prep(reg), alloc_reset_h()),
-    ]
-    // Unwrap all of the above `Result<Hugr, BuildError>` types into `Hugr`
-    .iter()
-    .map(|rule| (rule.0.clone().unwrap(), rule.1.clone().unwrap()))
-    .collect();
-    // Apply them exhaustively
-    apply_rules_exhaustively(rules, circ);
-}
\ No newline at end of file
+use std::collections::{hash_map::DefaultHasher, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use hugr::{Hugr, HugrView, extension::ExtensionRegistry};
+use serde::{Deserialize, Serialize};
+
+use crate::clifford::{correction_rules, CliffordGate};
+use crate::flow::remove_pauli_chains;
+use crate::pattern::{Pattern, PatternError};
+use crate::rule_set::{load_binary, save_binary, RuleSet, RuleSetError};
+use crate::rules::{parse_stages, Rule, Stage};
+use crate::utils::{apply_stages_with_config, op_name, RewriteConfig, RewriteError};
+
+
+/// The rule sets of every rewrite pass, one for each stage of the pass.
+///
+/// Building them takes much longer than applying them to a small circuit, so
+/// they are built once with `MbqcRules::new` (or loaded from disk with
+/// `MbqcRules::load`) and passed to each pass.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MbqcRules {
+    pub cx_to_cz: Vec<RuleSet>,
+    pub reduce_hadamards: Vec<RuleSet>,
+    pub absorb_paulis: Vec<RuleSet>,
+    pub to_mbqc: Vec<RuleSet>,
+    pub push_corrections_and_s_gates: Vec<RuleSet>,
+    pub propagate_corrections: Vec<RuleSet>,
+    pub remove_pauli_measurements: Vec<RuleSet>,
+    pub prep_to_alloc: Vec<RuleSet>,
+    /// The hash of the rules the rule sets were built from (see `rules_hash`),
+    /// which tells whether a saved copy is out of date.
+    pub rules_hash: u64,
+    /// How the passes apply the rule sets, e.g. the number of threads used.
+    #[serde(skip)]
+    pub config: RewriteConfig,
+}
+
+impl MbqcRules {
+    /// Build the rule sets from the rule files in `src/rules/`.
+    pub fn new(reg: &ExtensionRegistry) -> Self {
+        let mut passes: HashMap<&str, Vec<RuleSet>> = pass_stages(reg)
+            .into_iter()
+            .map(|(pass, stages)| (pass, stages.into_iter().map(RuleSet::from).collect()))
+            .collect();
+        let mut pass = |name: &str| passes.remove(name).unwrap_or_else(|| panic!("no rules for pass {name}"));
+
+        MbqcRules {
+            cx_to_cz: pass("cx_to_cz"),
+            reduce_hadamards: pass("reduce_hadamards"),
+            absorb_paulis: pass("absorb_paulis"),
+            to_mbqc: pass("to_mbqc"),
+            push_corrections_and_s_gates: pass("push_corrections_and_s_gates"),
+            propagate_corrections: pass("propagate_corrections"),
+            remove_pauli_measurements: pass("remove_pauli_measurements"),
+            prep_to_alloc: pass("prep_to_alloc"),
+            rules_hash: rules_hash(),
+            config: RewriteConfig::default(),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RuleSetError> {
+        save_binary(self, path)
+    }
+
+    /// Load the rule sets saved by `save`, failing if they were built from
+    /// other rules than those `MbqcRules::new` would build now.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RuleSetError> {
+        let rules: MbqcRules = load_binary(path)?;
+        if rules.rules_hash != rules_hash() {
+            return Err(RuleSetError::Outdated);
+        }
+        let passes = [
+            &rules.cx_to_cz,
+            &rules.reduce_hadamards,
+            &rules.absorb_paulis,
+            &rules.to_mbqc,
+            &rules.push_corrections_and_s_gates,
+            &rules.propagate_corrections,
+            &rules.remove_pauli_measurements,
+            &rules.prep_to_alloc,
+        ];
+        for rule_set in passes.into_iter().flatten() {
+            rule_set.check()?;
+        }
+        Ok(rules)
+    }
+}
+
+/// The rule file of every rewrite pass, by pass name, in the order the passes
+/// are applied. The push pass also gets the rules generated for every
+/// `CliffordGate`.
+const RULE_FILES: [(&str, &str); 8] = [
+    ("cx_to_cz", include_str!("rules/cx_to_cz.rules")),
+    ("reduce_hadamards", include_str!("rules/reduce_hadamards.rules")),
+    ("absorb_paulis", include_str!("rules/absorb_paulis.rules")),
+    ("to_mbqc", include_str!("rules/to_mbqc.rules")),
+    ("push_corrections_and_s_gates", include_str!("rules/push_s_gates.rules")),
+    ("propagate_corrections", include_str!("rules/propagate_corrections.rules")),
+    ("remove_pauli_measurements", include_str!("rules/remove_pauli_measurements.rules")),
+    ("prep_to_alloc", include_str!("rules/prep_to_alloc.rules")),
+];
+
+/// A hash of the sources the rules of every pass are built from, i.e. the rule
+/// files and the gates the correction rules are generated for, so that it
+/// changes with them without parsing any rule.
+pub fn rules_hash() -> u64 {
+    let mut hasher = DefaultHasher::new();
+    RULE_FILES.hash(&mut hasher);
+    CliffordGate::ALL.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The stages of rules of every rewrite pass, by pass name, in the order the
+/// passes are applied.
+pub fn pass_stages(reg: &ExtensionRegistry) -> Vec<(&'static str, Vec<Stage>)> {
+    RULE_FILES
+        .into_iter()
+        .map(|(pass, source)| {
+            let mut stages = parse_stages(source, reg).unwrap();
+            // Push corrections through each Clifford gate, following how the gate conjugates Paulis
+            if pass == "push_corrections_and_s_gates" {
+                for gate in CliffordGate::ALL {
+                    for (i, (lhs, rhs)) in correction_rules(&[gate], reg).unwrap().into_iter().enumerate() {
+                        let name = format!("{gate:?}_correction_{i}");
+                        stages.last_mut().unwrap().rules.push(Rule { name, lhs, rhs, priority: 0 });
+                    }
+                }
+            }
+            (pass, stages)
+        })
+        .collect()
+}
+
+pub fn cx_to_cz(circ: &mut Hugr, rules: &MbqcRules) -> Result<(), RewriteError> {
+    // Apply each stage of the rewrite rules exhaustively
+    apply_stages_with_config(&rules.cx_to_cz, circ, &rules.config, None)
+}
+
+/// Rewrite the Clifford gates of `circ` to reduce the number of H gates, each
+/// of which costs an ancilla in `to_mbqc`. Returns the number of H gates
+/// before and after.
+pub fn reduce_hadamards(circ: &mut Hugr, rules: &MbqcRules) -> Result<(usize, usize), RewriteError> {
+    let before = hadamard_count(circ);
+    // Apply each stage of the rewrite rules exhaustively
+    apply_stages_with_config(&rules.reduce_hadamards, circ, &rules.config, None)?;
+    Ok((before, hadamard_count(circ)))
+}
+
+/// The number of H gates in `circ`.
+fn hadamard_count(circ: &Hugr) -> usize {
+    circ.children(circ.root()).filter(|node| op_name(circ, *node) == "H").count()
+}
+
+pub fn absorb_paulis(circ: &mut Hugr, rules: &MbqcRules) -> Result<(), RewriteError> {
+    // Apply each stage of the rewrite rules exhaustively
+    apply_stages_with_config(&rules.absorb_paulis, circ, &rules.config, None)
+}
+
+pub fn to_mbqc(circ: &mut Hugr, rules: &MbqcRules) -> Result<(), RewriteError> {
+    // Apply each stage of the rewrite rules exhaustively
+    apply_stages_with_config(&rules.to_mbqc, circ, &rules.config, None)
+}
+
+pub fn push_corrections_and_s_gates(circ: &mut Hugr, rules: &MbqcRules) -> Result<(), RewriteError> {
+    // Apply each stage of the rewrite rules exhaustively
+    apply_stages_with_config(&rules.push_corrections_and_s_gates, circ, &rules.config, None)
+}
+
+pub fn propagate_corrections(circ: &mut Hugr, rules: &MbqcRules) -> Result<(), RewriteError> {
+    // Apply each stage of the rewrite rules exhaustively
+    apply_stages_with_config(&rules.propagate_corrections, circ, &rules.config, None)
+}
+
+pub fn remove_pauli_measurements(circ: &mut Hugr, rules: &MbqcRules, reg: &ExtensionRegistry) -> Result<usize, RewriteError> {
+    // Remove the Pauli-measured qubits, replacing their outcomes with constant false signals
+    let removed = remove_pauli_chains(circ, reg);
+    // Apply each stage of the rewrite rules exhaustively
+    apply_stages_with_config(&rules.remove_pauli_measurements, circ, &rules.config, None)?;
+    Ok(removed)
+}
+
+pub fn standardise(circ: &mut Hugr, reg: &ExtensionRegistry) -> Result<(), PatternError> {
+    // Translate to the measurement calculus, where commands can be reordered freely
+    let pattern = Pattern::from_hugr(circ)?.standardise()?;
+    // Rebuild the HUGR with all preparations first, then CZs, then measurements and then corrections
+    *circ = pattern.to_hugr(reg)?;
+    Ok(())
+}
+
+pub fn signal_shift(circ: &mut Hugr, reg: &ExtensionRegistry) -> Result<usize, PatternError> {
+    // Move the Z-type dependencies of measurements onto later commands
+    let pattern = Pattern::from_hugr(circ)?.signal_shift();
+    *circ = pattern.to_hugr(reg)?;
+    // Report the number of rounds of measurements needed
+    Ok(pattern.measurement_depth())
+}
+
+pub fn prep_to_alloc(circ: &mut Hugr, rules: &MbqcRules) -> Result<(), RewriteError> {
+    // Apply each stage of the rewrite rules exhaustively
+    apply_stages_with_config(&rules.prep_to_alloc, circ, &rules.config, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circ_example;
+    use crate::test_utils::registry;
+
+    #[test]
+    fn reduce_hadamards_drops_h_count() {
+        let reg = registry();
+        let rules = MbqcRules::new(&reg);
+        // The H gates around the CZ and S on q3 become a single one
+        let mut circ = circ_example().unwrap();
+        cx_to_cz(&mut circ, &rules).unwrap();
+        assert_eq!(reduce_hadamards(&mut circ, &rules).unwrap(), (5, 4));
+
+        // Computing and uncomputing a parity along a CX ladder, whose H gates do not cancel once written with CZ gates
+        let mut ladder =
+            crate::circuit!(&reg; q0, q1, q2, q3 => CX(q0, q1); CX(q1, q2); CX(q2, q3); CX(q1, q2); CX(q0, q1)).unwrap();
+        cx_to_cz(&mut ladder, &rules).unwrap();
+        let (before, after) = reduce_hadamards(&mut ladder, &rules).unwrap();
+        assert_eq!(before, 10);
+        assert!(after < before, "{after} H gates left");
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use hugr::{Hugr, HugrView, PortIndex};
+use portmatching::PatternID;
+use serde::{Deserialize, Serialize};
+use tket2::portmatching::{CircuitPattern, PatternMatcher};
+
+use crate::commutation::lhs_adjacencies;
+use crate::rules::{Rule, Stage};
+
+/// A set of rewrite rules ready to be applied, with the pattern matcher for
+/// their LHS already built.
+///
+/// Building the matcher is the most expensive part of setting up a rewrite
+/// pass, so a `RuleSet` is meant to be built once and reused on every circuit.
+/// It can also be saved to disk with `save_binary` and loaded back without
+/// rebuilding the matcher.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub name: String,
+    /// The name of each rule, by index.
+    pub rule_names: Vec<String>,
+    /// The priority of each rule, by index. When the matches of two rules
+    /// overlap, the rule with the higher priority is applied first.
+    pub priorities: Vec<i32>,
+    /// The RHS of each rule, by index.
+    pub rhs: Vec<Hugr>,
+    pub matcher: PatternMatcher,
+    /// Every node of a match is within this distance of its root.
+    pub radius: usize,
+    /// Whether the RHS of each rule, by index, links the same inputs to the
+    /// same outputs as its LHS and has every node linked to them, so that the
+    /// matches after its rewrites can be found incrementally (see
+    /// `find_matches_near`).
+    pub keeps_paths: Vec<bool>,
+    /// Pairs of operations that commute on a shared qubit wire.
+    pub commutations: Vec<(String, String)>,
+    /// Pairs of single-qubit operations directly connected in some LHS, which
+    /// are brought together by sliding through commuting operations.
+    pub adjacencies: Vec<(String, String)>,
+}
+
+/// An error found while saving or loading a rule set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleSetError {
+    /// The file could not be read or written.
+    Io(String),
+    /// The rule set could not be encoded or decoded.
+    Encoding(String),
+    /// The rule set was built from other rules than the current ones.
+    Outdated,
+}
+
+impl fmt::Display for RuleSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleSetError::Io(message) => write!(f, "cannot access rule set file: {message}"),
+            RuleSetError::Encoding(message) => write!(f, "invalid rule set: {message}"),
+            RuleSetError::Outdated => write!(f, "rule set built from other rules"),
+        }
+    }
+}
+
+impl RuleSet {
+    /// Build the rule set for `rules`, in the order given.
+    pub fn new(name: impl Into<String>, rules: Vec<Rule>) -> Self {
+        // Translate the LHS from HUGRs to patterns
+        let mut lhs_of_rules = vec![];
+        for rule in rules.iter() {
+            lhs_of_rules.push(
+                CircuitPattern::try_from_circuit(&rule.lhs).unwrap()
+            );
+        }
+        let radius = rules.iter().map(|rule| rule.lhs.children(rule.lhs.root()).count() - 2).max().unwrap_or(0);
+        let keeps_paths = rules
+            .iter()
+            .map(|rule| port_paths(&rule.lhs) == port_paths(&rule.rhs) && is_anchored(&rule.rhs))
+            .collect();
+        let mut adjacencies: Vec<(String, String)> = rules.iter().flat_map(|rule| lhs_adjacencies(&rule.lhs)).collect();
+        adjacencies.sort();
+        adjacencies.dedup();
+
+        RuleSet {
+            name: name.into(),
+            rule_names: rules.iter().map(|rule| rule.name.clone()).collect(),
+            priorities: rules.iter().map(|rule| rule.priority).collect(),
+            rhs: rules.into_iter().map(|rule| rule.rhs).collect(),
+            matcher: PatternMatcher::from_patterns(lhs_of_rules),
+            radius,
+            keeps_paths,
+            commutations: vec![],
+            adjacencies,
+        }
+    }
+
+    /// Build the rule set for unnamed `(LHS, RHS)` pairs, all with priority 0.
+    pub fn from_pairs(name: impl Into<String>, rules: Vec<(Hugr, Hugr)>) -> Self {
+        let rules = rules
+            .into_iter()
+            .enumerate()
+            .map(|(i, (lhs, rhs))| Rule { name: format!("rule {i}"), lhs, rhs, priority: 0 })
+            .collect();
+        Self::new(name, rules)
+    }
+
+    /// The priority of the rule `rule_id`.
+    pub fn priority(&self, rule_id: usize) -> i32 {
+        self.priorities[rule_id]
+    }
+
+    /// Check that every rule has a name, a priority, a pattern for its LHS and
+    /// a RHS, as a rule set loaded from disk may not.
+    pub fn check(&self) -> Result<(), RuleSetError> {
+        let n = self.rule_names.len();
+        let n_patterns = (0..).find(|i| self.matcher.get_pattern(PatternID(*i)).is_none()).unwrap();
+        if n_patterns != n {
+            return Err(RuleSetError::Encoding(format!(
+                "rule set {} has {n} rules but its matcher has {n_patterns} patterns",
+                self.name
+            )));
+        }
+        if self.priorities.len() != n || self.rhs.len() != n || self.keeps_paths.len() != n {
+            return Err(RuleSetError::Encoding(format!(
+                "rule set {} has {n} rules but {} priorities, {} RHS and {} path flags",
+                self.name,
+                self.priorities.len(),
+                self.rhs.len(),
+                self.keeps_paths.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The pairs `(i, j)` such that a path in `circ` leads from its input `i` to
+/// its output `j`.
+fn port_paths(circ: &Hugr) -> BTreeSet<(usize, usize)> {
+    let [input, output] = circ.get_io(circ.root()).unwrap();
+    let mut paths = BTreeSet::new();
+    for p in circ.node_outputs(input) {
+        let mut stack: Vec<_> = circ.linked_ports(input, p).collect();
+        let mut seen = HashSet::new();
+        while let Some((node, port)) = stack.pop() {
+            if node == output {
+                paths.insert((p.index(), port.index()));
+            } else if seen.insert(node) {
+                for q in circ.node_outputs(node) {
+                    stack.extend(circ.linked_ports(node, q));
+                }
+            }
+        }
+    }
+    paths
+}
+
+/// Whether every node of `circ` is linked, through other nodes, to its input
+/// or output node.
+fn is_anchored(circ: &Hugr) -> bool {
+    let mut stack = circ.get_io(circ.root()).unwrap().to_vec();
+    let mut seen = HashSet::new();
+    while let Some(node) = stack.pop() {
+        if seen.insert(node) {
+            for p in circ.all_node_ports(node) {
+                stack.extend(circ.linked_ports(node, p).map(|(n, _)| n));
+            }
+        }
+    }
+    circ.children(circ.root()).all(|n| seen.contains(&n))
+}
+
+/// Save rule sets (or anything holding them) to disk in a binary format.
+pub fn save_binary(value: &impl Serialize, path: impl AsRef<Path>) -> Result<(), RuleSetError> {
+    let bytes = rmp_serde::to_vec(value).map_err(|e| RuleSetError::Encoding(e.to_string()))?;
+    std::fs::write(path, bytes).map_err(|e| RuleSetError::Io(e.to_string()))
+}
+
+/// Load rule sets (or anything holding them) saved by `save_binary`.
+pub fn load_binary<T: for<'de> Deserialize<'de>>(path: impl AsRef<Path>) -> Result<T, RuleSetError> {
+    let bytes = std::fs::read(path).map_err(|e| RuleSetError::Io(e.to_string()))?;
+    rmp_serde::from_slice(&bytes).map_err(|e| RuleSetError::Encoding(e.to_string()))
+}
+
+impl From<Stage> for RuleSet {
+    fn from(stage: Stage) -> Self {
+        let mut rule_set = RuleSet::new(stage.name, stage.rules);
+        rule_set.commutations = stage.commutations;
+        rule_set
+    }
+}
@@ -0,0 +1,669 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use hugr::{
+    builder::{BuildError, DFGBuilder, Dataflow, DataflowHugr},
+    extension::{prelude::QB_T, ExtensionRegistry},
+    ops::OpTrait,
+    types::{FunctionType, Type},
+    Hugr, Wire,
+};
+use tket2::circuit::CircuitHash;
+
+use crate::termination::Measure;
+use crate::utils::op_from_name;
+
+/// A rewrite rule read from a rule file.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub lhs: Hugr,
+    pub rhs: Hugr,
+    /// When the matches of two rules overlap, the rule with the higher
+    /// priority is applied first.
+    pub priority: i32,
+}
+
+/// A set of rules applied exhaustively before moving on to the next stage.
+#[derive(Debug, Clone)]
+pub struct Stage {
+    pub name: String,
+    pub rules: Vec<Rule>,
+    /// Pairs of operations that commute when acting on the same qubit wire,
+    /// which the rules are matched modulo.
+    pub commutations: Vec<(String, String)>,
+    /// Measures of the circuit that the rules are meant to decrease, in
+    /// lexicographic order (see `check_termination`).
+    pub orderings: Vec<Measure>,
+}
+
+/// An error found while reading a rule file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleError {
+    /// The rule file could not be read.
+    Io(String),
+    /// A line of the rule file could not be parsed.
+    Parse { line: usize, message: String },
+    /// A circuit given to `parse_circuit` could not be parsed or built.
+    Circuit(String),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::Io(message) => write!(f, "cannot read rule file: {message}"),
+            RuleError::Parse { line, message } => write!(f, "line {line}: {message}"),
+            RuleError::Circuit(message) => write!(f, "invalid circuit: {message}"),
+        }
+    }
+}
+
+/// Build a small HUGR from a list of statements, in the same syntax as each
+/// side of a rule in a rule file (see `parse_rules`).
+///
+/// The inputs are listed before `=>`, each one a qubit unless its type is given
+/// (only `MyBool` is supported), and the outputs are the variables left unused
+/// at the end, in the order they are first mentioned. For instance
+/// ```ignore
+/// parse_circuit("q, c: MyBool => CorrectionX(q, c); H(q)", reg)
+/// ```
+/// builds a `CorrectionX` followed by an `H` gate, with inputs `q` and `c` and
+/// output `q`. The statements may span several lines, and everything after a
+/// `#` in a line is a comment. The `circuit!` macro builds the same HUGRs from
+/// Rust tokens instead of a string.
+pub fn parse_circuit(source: &str, registry: &ExtensionRegistry) -> Result<Hugr, RuleError> {
+    let lines: Vec<&str> = source.lines().map(|line| line.split('#').next().unwrap()).collect();
+    let source = lines.join(" ");
+    let (inputs, body) = source
+        .split_once("=>")
+        .ok_or(RuleError::Circuit("expected `INPUTS => STATEMENTS`".to_string()))?;
+    let inputs: Vec<(&str, &str)> = inputs
+        .split(',')
+        .filter(|input| !input.trim().is_empty())
+        .map(|input| input.split_once(':').unwrap_or((input, "")))
+        .collect();
+    let (statements, inputs, outputs) = circuit_signature(&inputs, body, registry).map_err(RuleError::Circuit)?;
+    build(&statements, &inputs, &outputs, registry).map_err(|e| RuleError::Circuit(e.to_string()))
+}
+
+/// Build a small HUGR from a list of statements, as `parse_circuit` does from
+/// a string. For instance
+/// ```ignore
+/// circuit!(reg; q, c: MyBool => CorrectionX(q, c); H(q))
+/// ```
+/// builds a `CorrectionX` followed by an `H` gate, with inputs `q` and `c` and
+/// output `q`. Returns the `BuildError` of the builder if the HUGR is invalid,
+/// and panics if the statements are malformed, e.g. if an operation is unknown
+/// or a variable is used twice.
+#[macro_export]
+macro_rules! circuit {
+    ($reg:expr; $($input:ident $(: $ty:ident)?),* => $($body:tt)*) => {
+        $crate::rules::build_circuit(
+            $reg,
+            &[$((stringify!($input), stringify!($($ty)?))),*],
+            stringify!($($body)*),
+        )
+    };
+}
+
+/// The function behind the `circuit!` macro.
+pub fn build_circuit(registry: &ExtensionRegistry, inputs: &[(&str, &str)], body: &str) -> Result<Hugr, BuildError> {
+    let (statements, inputs, outputs) =
+        circuit_signature(inputs, body, registry).unwrap_or_else(|e| panic!("invalid circuit: {e}"));
+    build(&statements, &inputs, &outputs, registry)
+}
+
+/// Parse the statements of a circuit with the given inputs, each one a name
+/// and a type (empty for qubits), and find its outputs.
+fn circuit_signature(
+    inputs: &[(&str, &str)],
+    body: &str,
+    registry: &ExtensionRegistry,
+) -> Result<(Vec<Statement>, Vars, Vars), String> {
+    let inputs: Vars = inputs
+        .iter()
+        .map(|(var, ty)| -> Result<(String, Type), String> {
+            let ty = match ty.trim() {
+                "" => QB_T,
+                "MyBool" => {
+                    let extension = registry.get("ExtMBQC").ok_or("MyBool needs the ExtMBQC extension")?;
+                    Type::new_extension(extension.get_type("MyBool").unwrap().instantiate([]).unwrap())
+                }
+                ty => return Err(format!("unknown type {ty}")),
+            };
+            Ok((parse_name(var)?, ty))
+        })
+        .collect::<Result<_, String>>()?;
+    let statements = parse_side(body)?;
+    let (_, mut outputs, mentioned) = signature(&statements, Some(&inputs), registry)?;
+    outputs.sort_by_key(|(var, _)| mentioned.iter().position(|m| m == var));
+    Ok((statements, inputs, outputs))
+}
+
+/// Variables and their types, in order.
+type Vars = Vec<(String, Type)>;
+
+/// A statement on either side of a rule.
+#[derive(Debug, Clone)]
+enum Statement {
+    /// An operation applied to some variables. If the outputs are not named,
+    /// they are bound to the names of the arguments, in order.
+    Op { outputs: Option<Vec<String>>, name: String, args: Vec<String> },
+    /// A new name for a variable, as in `a = b`.
+    Alias { output: String, input: String },
+}
+
+/// Parse a rule file into the `(LHS, RHS)` pairs used by `apply_rules_exhaustively`.
+///
+/// Each line of the file holds a rule `name: LHS => RHS`, and everything after
+/// a `#` is a comment. Each side is a list of statements separated by `;`,
+/// such as `H(q)`, `m = MeasureX(q)`, `a, b = Copy(c)` or `a = b`. Operations
+/// are either `Tk2Op` gates or operations from the `ExtMBQC` extension. Each
+/// variable is a wire, consumed by the statement it is passed to; when the
+/// outputs of an operation are not named, they take the names of its arguments.
+///
+/// The variables used by the LHS before being defined are the inputs of the
+/// rule, and the variables left unused at the end are its outputs. Their types
+/// are inferred from the operations. The RHS must take the same inputs and
+/// leave the same outputs as the LHS.
+///
+/// A line `symmetric CZ XOR Copy` declares that the listed operations are
+/// symmetric in their two arguments (or, for `Copy`, its two outputs). Every
+/// rule after it is then expanded into all the distinct variants of its LHS
+/// where the arguments and outputs of symmetric operations are swapped, so
+/// mirror images of a rule need not be written by hand.
+///
+/// The rules can also be split into stages and given priorities, see
+/// `parse_stages`; this function ignores both.
+pub fn parse_rules(source: &str, registry: &ExtensionRegistry) -> Result<Vec<(Hugr, Hugr)>, RuleError> {
+    let rules = parse_named_rules(source, registry)?;
+    Ok(rules.into_iter().map(|rule| (rule.lhs, rule.rhs)).collect())
+}
+
+/// Parse a rule file, keeping the name of each rule. See `parse_rules`.
+pub fn parse_named_rules(source: &str, registry: &ExtensionRegistry) -> Result<Vec<Rule>, RuleError> {
+    let stages = parse_stages(source, registry)?;
+    Ok(stages.into_iter().flat_map(|stage| stage.rules).collect())
+}
+
+/// Parse a rule file into the stages of a rewrite strategy.
+///
+/// On top of the format described in `parse_rules`, a line `stage NAME` starts
+/// a new stage, and a line `priority N` sets the priority of the rules after
+/// it, up to the next `priority` or `stage` line. Rules before the first
+/// `stage` line go in a stage called `main`, and rules have priority 0 unless
+/// stated otherwise. The stages are meant to be applied one after another,
+/// each of them exhaustively (see `apply_stages`).
+///
+/// A line `commute A B` declares that the single-qubit operation `A` commutes
+/// with `B` (up to a global phase) when they act on the same qubit wire, e.g.
+/// `commute CorrectionX CorrectionZ` or `commute S CZ`. The rules of every
+/// stage in the file are then matched modulo these commutations (see
+/// `slide_commuting`).
+///
+/// A line `order count A`, `order weights A=2 B=1` or `order outputs A B`
+/// declares a measure of the circuit that the rules of the current stage
+/// decrease: the number of `A` operations, the sum of the weights of the
+/// operations, or how far the listed operations are from the outputs (see
+/// `Measure`). Later `order` lines are only used to compare rules that leave
+/// the earlier measures unchanged.
+pub fn parse_stages(source: &str, registry: &ExtensionRegistry) -> Result<Vec<Stage>, RuleError> {
+    let mut stages = vec![Stage { name: "main".to_string(), rules: vec![], commutations: vec![], orderings: vec![] }];
+    let mut symmetric = HashSet::new();
+    let mut commutations = vec![];
+    let mut priority = 0;
+    for (i, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message| RuleError::Parse { line: i + 1, message };
+        if let Some(ops) = line.strip_prefix("symmetric ") {
+            for op in ops.split_whitespace() {
+                op_from_name(op, registry).ok_or_else(|| error(format!("unknown operation {op}")))?;
+                symmetric.insert(op.to_string());
+            }
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("stage ") {
+            // The rules before the first stage line, if any, form a stage of their own
+            if stages.len() == 1 && stages[0].rules.is_empty() {
+                stages.clear();
+            }
+            stages.push(Stage { name: name.trim().to_string(), rules: vec![], commutations: vec![], orderings: vec![] });
+            priority = 0;
+            continue;
+        }
+        if let Some(ops) = line.strip_prefix("commute ") {
+            let ops: Vec<&str> = ops.split_whitespace().collect();
+            let [a, b] = ops[..] else {
+                return Err(error("expected `commute A B`".to_string()));
+            };
+            for op in [a, b] {
+                op_from_name(op, registry).ok_or_else(|| error(format!("unknown operation {op}")))?;
+            }
+            commutations.push((a.to_string(), b.to_string()));
+            continue;
+        }
+        if let Some(order) = line.strip_prefix("order ") {
+            let measure = parse_measure(order, registry).map_err(error)?;
+            stages.last_mut().unwrap().orderings.push(measure);
+            continue;
+        }
+        if let Some(n) = line.strip_prefix("priority ") {
+            priority = n.trim().parse().map_err(|_| error(format!("invalid priority {}", n.trim())))?;
+            continue;
+        }
+        let mut rules = parse_rule(line, &symmetric, registry).map_err(error)?;
+        for rule in rules.iter_mut() {
+            rule.priority = priority;
+        }
+        stages.last_mut().unwrap().rules.extend(rules);
+    }
+    for stage in stages.iter_mut() {
+        stage.commutations = commutations.clone();
+    }
+    Ok(stages)
+}
+
+/// Read a rule file from disk, keeping the name and priority of each rule and
+/// the stages. See `parse_stages` for the format.
+pub fn load_stages(path: impl AsRef<Path>, registry: &ExtensionRegistry) -> Result<Vec<Stage>, RuleError> {
+    let source = std::fs::read_to_string(path).map_err(|e| RuleError::Io(e.to_string()))?;
+    parse_stages(&source, registry)
+}
+
+/// Parse the measure declared by an `order` line, without the `order` keyword.
+fn parse_measure(line: &str, registry: &ExtensionRegistry) -> Result<Measure, String> {
+    let mut words = line.split_whitespace();
+    let kind = words.next().unwrap_or_default();
+    let args: Vec<&str> = words.collect();
+    let check_op = |op: &str| match op_from_name(op, registry) {
+        Some(_) => Ok(op.to_string()),
+        None => Err(format!("unknown operation {op}")),
+    };
+    match (kind, &args[..]) {
+        ("count", [op]) => Ok(Measure::Count(check_op(*op)?)),
+        ("weights", _) if !args.is_empty() => {
+            let mut weights = BTreeMap::new();
+            for arg in args.iter() {
+                let (op, w) = arg.split_once('=').ok_or(format!("expected `OP=WEIGHT`, found {arg}"))?;
+                let w: i64 = w.parse().ok().filter(|w| *w >= 0).ok_or(format!("invalid weight {w}"))?;
+                weights.insert(check_op(op)?, w);
+            }
+            Ok(Measure::Weights(weights))
+        }
+        ("outputs", _) if !args.is_empty() => {
+            let names = args.iter().map(|op| check_op(op)).collect::<Result<_, _>>()?;
+            Ok(Measure::TowardOutputs(names))
+        }
+        _ => Err("expected `order count A`, `order weights A=W ...` or `order outputs A ...`".to_string()),
+    }
+}
+
+/// Parse a rule, together with its variants if it involves symmetric operations.
+fn parse_rule(line: &str, symmetric: &HashSet<String>, registry: &ExtensionRegistry) -> Result<Vec<Rule>, String> {
+    let (name, body) = line.split_once(':').ok_or("expected `name: LHS => RHS`")?;
+    let (lhs, rhs) = body.split_once("=>").ok_or("expected `name: LHS => RHS`")?;
+    let lhs = parse_side(lhs)?;
+    let rhs = parse_side(rhs)?;
+
+    // Variants whose LHS is the same circuit as an earlier one (e.g. swapping the arguments of
+    // an `XOR` whose inputs are both inputs of the rule) would only duplicate the rewrites
+    let mut rules: Vec<Rule> = vec![];
+    let mut seen = HashSet::new();
+    for lhs in symmetric_variants(&lhs, symmetric) {
+        let mut rule = build_rule(&lhs, &rhs, registry)?;
+        if !seen.insert(rule.lhs.circuit_hash()) {
+            continue;
+        }
+        rule.name = match rules.len() {
+            0 => name.trim().to_string(),
+            i => format!("{} (variant {i})", name.trim()),
+        };
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+/// All the ways of swapping the arguments and outputs of the symmetric
+/// operations in a list of statements, starting with the statements as given.
+fn symmetric_variants(statements: &[Statement], symmetric: &HashSet<String>) -> Vec<Vec<Statement>> {
+    let swap = |mut names: Vec<String>| {
+        if names.len() == 2 {
+            names.swap(0, 1);
+        }
+        names
+    };
+    let mut variants = vec![statements.to_vec()];
+    for (i, statement) in statements.iter().enumerate() {
+        let Statement::Op { outputs, name, args } = statement else {
+            continue;
+        };
+        if !symmetric.contains(name) {
+            continue;
+        }
+        // Unnamed outputs follow the arguments, so they are swapped along with them
+        let swapped = Statement::Op {
+            outputs: outputs.clone().map(swap),
+            name: name.clone(),
+            args: swap(args.clone()),
+        };
+        let mirrored: Vec<Vec<Statement>> = variants
+            .iter()
+            .map(|variant| {
+                let mut variant = variant.clone();
+                variant[i] = swapped.clone();
+                variant
+            })
+            .collect();
+        variants.extend(mirrored);
+    }
+    variants
+}
+
+fn build_rule(lhs: &[Statement], rhs: &[Statement], registry: &ExtensionRegistry) -> Result<Rule, String> {
+    // The LHS determines the inputs and outputs of the rule, listed in the order they are mentioned
+    let (inputs, mut outputs, mentioned) = signature(lhs, None, registry)?;
+    outputs.sort_by_key(|(var, _)| mentioned.iter().position(|m| m == var));
+
+    // The RHS must leave the same outputs
+    let (_, mut rhs_outputs, _) = signature(rhs, Some(&inputs), registry)?;
+    rhs_outputs.sort_by_key(|(var, _)| outputs.iter().position(|(o, _)| o == var));
+    if rhs_outputs != outputs {
+        let names = |vars: &[(String, Type)]| vars.iter().map(|(v, _)| v.clone()).collect::<Vec<_>>().join(", ");
+        return Err(format!("the LHS outputs [{}] but the RHS outputs [{}]", names(&outputs), names(&rhs_outputs)));
+    }
+
+    Ok(Rule {
+        name: String::new(),
+        lhs: build(lhs, &inputs, &outputs, registry).map_err(|e| e.to_string())?,
+        rhs: build(rhs, &inputs, &outputs, registry).map_err(|e| e.to_string())?,
+        priority: 0,
+    })
+}
+
+fn parse_side(side: &str) -> Result<Vec<Statement>, String> {
+    side.split(';')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(parse_statement)
+        .collect()
+}
+
+fn parse_statement(statement: &str) -> Result<Statement, String> {
+    let (outputs, call) = match statement.split_once('=') {
+        Some((outputs, call)) => (Some(parse_names(outputs)?), call.trim()),
+        None => (None, statement),
+    };
+    let Some((name, args)) = call.split_once('(') else {
+        // Without parentheses, this must be an alias
+        return match outputs.as_deref() {
+            Some([output]) => Ok(Statement::Alias { output: output.clone(), input: parse_name(call)? }),
+            _ => Err(format!("cannot parse `{statement}`")),
+        };
+    };
+    let args = args.strip_suffix(')').ok_or(format!("missing `)` in `{statement}`"))?;
+    let args = if args.trim().is_empty() { vec![] } else { parse_names(args)? };
+    Ok(Statement::Op { outputs, name: parse_name(name)?, args })
+}
+
+fn parse_names(names: &str) -> Result<Vec<String>, String> {
+    names.split(',').map(parse_name).collect()
+}
+
+fn parse_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("`{name}` is not a valid name"));
+    }
+    Ok(name.to_string())
+}
+
+/// The names bound to the outputs of an operation.
+fn output_names(outputs: &Option<Vec<String>>, name: &str, args: &[String], n_outputs: usize) -> Result<Vec<String>, String> {
+    let names = match outputs {
+        Some(outputs) => outputs.clone(),
+        None if n_outputs <= args.len() => args[..n_outputs].to_vec(),
+        None => return Err(format!("the outputs of {name} must be named")),
+    };
+    if names.len() != n_outputs {
+        return Err(format!("{name} has {n_outputs} outputs, but {} names are given", names.len()));
+    }
+    Ok(names)
+}
+
+/// Follow the statements on one side of a rule, keeping track of the type of
+/// each variable.
+///
+/// If `inputs` is `None`, any variable used before being defined becomes an
+/// input. Returns the inputs, the variables left at the end and all the
+/// variables in the order they are first mentioned.
+fn signature(
+    statements: &[Statement],
+    inputs: Option<&[(String, Type)]>,
+    registry: &ExtensionRegistry,
+) -> Result<(Vars, Vars, Vec<String>), String> {
+    let infer = inputs.is_none();
+    let mut inputs = inputs.map(|i| i.to_vec()).unwrap_or_default();
+    let mut live = inputs.clone();
+    let mut mentioned: Vec<String> = inputs.iter().map(|(v, _)| v.clone()).collect();
+
+    // Consume a variable, checking its type
+    let mut take = |var: &String, ty: Option<&Type>, live: &mut Vars, mentioned: &mut Vec<String>| {
+        if let Some(i) = live.iter().position(|(v, _)| v == var) {
+            let (_, found) = live.remove(i);
+            match ty {
+                Some(ty) if *ty != found => Err(format!("{var} has type {found:?}, expected {ty:?}")),
+                _ => Ok(found),
+            }
+        } else if mentioned.contains(var) || !infer {
+            Err(format!("{var} is not available"))
+        } else if let Some(ty) = ty {
+            mentioned.push(var.clone());
+            inputs.push((var.clone(), ty.clone()));
+            Ok(ty.clone())
+        } else {
+            Err(format!("cannot infer the type of {var}"))
+        }
+    };
+    // Define a variable
+    let define = |var: String, ty: Type, live: &mut Vars, mentioned: &mut Vec<String>| {
+        if live.iter().any(|(v, _)| *v == var) {
+            return Err(format!("{var} is defined twice"));
+        }
+        if !mentioned.contains(&var) {
+            mentioned.push(var.clone());
+        }
+        live.push((var, ty));
+        Ok(())
+    };
+
+    for statement in statements {
+        match statement {
+            Statement::Op { outputs, name, args } => {
+                let op = op_from_name(name, registry).ok_or(format!("unknown operation {name}"))?;
+                let sig = op.dataflow_signature().unwrap();
+                if args.len() != sig.input().len() {
+                    return Err(format!("{name} takes {} arguments", sig.input().len()));
+                }
+                for (arg, ty) in args.iter().zip(sig.input().iter()) {
+                    take(arg, Some(ty), &mut live, &mut mentioned)?;
+                }
+                for (var, ty) in output_names(outputs, name, args, sig.output().len())?.into_iter().zip(sig.output().iter()) {
+                    define(var, ty.clone(), &mut live, &mut mentioned)?;
+                }
+            }
+            Statement::Alias { output, input } => {
+                let ty = take(input, None, &mut live, &mut mentioned)?;
+                define(output.clone(), ty, &mut live, &mut mentioned)?;
+            }
+        }
+    }
+    Ok((inputs, live, mentioned))
+}
+
+/// Build the HUGR for one side of a rule.
+///
+/// The statements must have been checked by `signature` first.
+fn build(
+    statements: &[Statement],
+    inputs: &[(String, Type)],
+    outputs: &[(String, Type)],
+    registry: &ExtensionRegistry,
+) -> Result<Hugr, BuildError> {
+    let input_types: Vec<Type> = inputs.iter().map(|(_, ty)| ty.clone()).collect();
+    let output_types: Vec<Type> = outputs.iter().map(|(_, ty)| ty.clone()).collect();
+    let mut h = DFGBuilder::new(FunctionType::new(input_types, output_types))?;
+    let mut wires: HashMap<String, Wire> = inputs.iter().map(|(v, _)| v.clone()).zip(h.input_wires()).collect();
+
+    for statement in statements {
+        match statement {
+            Statement::Op { outputs, name, args } => {
+                let op = op_from_name(name, registry).unwrap();
+                let n_outputs = op.dataflow_signature().unwrap().output().len();
+                let args_wires: Vec<Wire> = args.iter().map(|a| wires.remove(a).unwrap()).collect();
+                let res = h.add_dataflow_op(op, args_wires)?;
+                for (i, var) in output_names(outputs, name, args, n_outputs).unwrap().into_iter().enumerate() {
+                    wires.insert(var, res.out_wire(i));
+                }
+            }
+            Statement::Alias { output, input } => {
+                let wire = wires.remove(input).unwrap();
+                wires.insert(output.clone(), wire);
+            }
+        }
+    }
+
+    let output_wires: Vec<Wire> = outputs.iter().map(|(v, _)| wires[v]).collect();
+    h.finish_hugr_with_outputs(output_wires, registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use hugr::HugrView;
+
+    use super::*;
+    use crate::test_utils::registry;
+    use crate::utils::op_name;
+
+    /// The operations on one side of a rule, in the order they were added.
+    fn ops(circ: &Hugr) -> Vec<String> {
+        let io = circ.get_io(circ.root()).unwrap();
+        circ.children(circ.root()).filter(|n| !io.contains(n)).map(|n| op_name(circ, n)).collect()
+    }
+
+    #[test]
+    fn parse_rule_sides() {
+        let source = "
+            # Comments and blank lines are skipped
+            h_h: H(q); H(q) =>  # H is self-inverse
+            x_z: CorrectionX(q, a); CorrectionZ(q, b) => CorrectionZ(q, b); CorrectionX(q, a)
+            copy: m = MeasureX(q); c, d = Copy(m) => n = MeasureX(q); c, d = Copy(n)
+        ";
+        let rules = parse_named_rules(source, &registry()).unwrap();
+        let names: Vec<&str> = rules.iter().map(|rule| rule.name.as_str()).collect();
+        assert_eq!(names, ["h_h", "x_z", "copy"]);
+        assert_eq!(ops(&rules[0].lhs), ["H", "H"]);
+        assert!(ops(&rules[0].rhs).is_empty());
+        assert_eq!(ops(&rules[1].lhs), ["CorrectionX", "CorrectionZ"]);
+        assert_eq!(ops(&rules[1].rhs), ["CorrectionZ", "CorrectionX"]);
+        assert_eq!(ops(&rules[2].rhs), ["MeasureX", "Copy"]);
+        assert_eq!(parse_rules(source, &registry()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn parse_circuits() {
+        let reg = registry();
+        let circ = parse_circuit("q, c: MyBool => CorrectionX(q, c)\n  H(q)  # on two lines", &reg);
+        assert!(matches!(circ, Err(RuleError::Circuit(_))));
+        let circ = parse_circuit("q, c: MyBool =>\n  CorrectionX(q, c);\n  H(q)  # on two lines", &reg).unwrap();
+        assert_eq!(ops(&circ), ["CorrectionX", "H"]);
+        for source in ["q => Foo(q)", "q => H(p)", "q: Int => H(q)", "q => H(q); H(q)) =>", "H(q)"] {
+            assert!(matches!(parse_circuit(source, &reg), Err(RuleError::Circuit(_))), "{source}");
+        }
+    }
+
+    #[test]
+    fn circuit_macro() {
+        let reg = registry();
+        let circ = crate::circuit!(&reg; q, r, c: MyBool => CorrectionX(q, c); m = MeasureX(r); CorrectionZ(q, m)).unwrap();
+        let parsed = parse_circuit("q, r, c: MyBool => CorrectionX(q, c); m = MeasureX(r); CorrectionZ(q, m)", &reg).unwrap();
+        assert_eq!(circ.circuit_hash(), parsed.circuit_hash());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid circuit")]
+    fn circuit_macro_panics_on_unknown_op() {
+        let _ = crate::circuit!(&registry(); q => Foo(q));
+    }
+
+    #[test]
+    fn report_parse_errors() {
+        let reg = registry();
+        let line_of = |source: &str| match parse_stages(source, &reg) {
+            Err(RuleError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {other:?}"),
+        };
+        // Unknown operation
+        assert_eq!(line_of("a: Foo(q) =>"), 1);
+        // Missing `;` between statements
+        assert_eq!(line_of("a: H(q); H(q) =>\n\nb: H(q) H(q) =>"), 3);
+        // Variable of the RHS that is not an input
+        assert_eq!(line_of("a: H(q) => X(q); Y(p)"), 1);
+        // Both sides leaving different outputs
+        assert_eq!(line_of("a: m = MeasureX(q) =>"), 1);
+        // Malformed directives
+        assert_eq!(line_of("commute X"), 1);
+        assert_eq!(line_of("priority high"), 1);
+        assert_eq!(line_of("order count"), 1);
+    }
+
+    #[test]
+    fn skip_identical_variants() {
+        let source = "
+            symmetric CZ XOR Copy
+            xor_discard: c = XOR(a, b); DiscardSignal(c) => DiscardSignal(a); DiscardSignal(b)
+            true_copy: t = ConstTrue(); a, b = Copy(t) => a = ConstTrue(); b = ConstTrue()
+            cz_z: CZ(a, b); Z(a) => Z(a); CZ(a, b)
+        ";
+        let rules = parse_named_rules(source, &registry()).unwrap();
+        let names: Vec<&str> = rules.iter().map(|rule| rule.name.as_str()).collect();
+        assert_eq!(names, ["xor_discard", "true_copy", "cz_z", "cz_z (variant 1)"]);
+    }
+
+    #[test]
+    fn parse_stages_and_priorities() {
+        let source = "
+            h_h: H(q); H(q) =>
+            stage second
+            order count X
+            priority 2
+            x_x: X(q); X(q) =>
+            priority -1
+            z_z: Z(q); Z(q) =>
+            stage third
+            y_y: Y(q); Y(q) =>
+            commute X Z
+        ";
+        let stages = parse_stages(source, &registry()).unwrap();
+        let names: Vec<&str> = stages.iter().map(|stage| stage.name.as_str()).collect();
+        assert_eq!(names, ["main", "second", "third"]);
+        let priorities: Vec<Vec<i32>> = stages.iter().map(|stage| stage.rules.iter().map(|rule| rule.priority).collect()).collect();
+        assert_eq!(priorities, [vec![0], vec![2, -1], vec![0]]);
+        assert_eq!(stages[1].orderings, [Measure::Count("X".to_string())]);
+        assert!(stages[0].orderings.is_empty());
+        // Commutations apply to every stage of the file
+        for stage in stages.iter() {
+            assert_eq!(stage.commutations, [("X".to_string(), "Z".to_string())]);
+        }
+    }
+
+    #[test]
+    fn no_main_stage_before_first_stage_line() {
+        let stages = parse_stages("stage only\nh_h: H(q); H(q) =>", &registry()).unwrap();
+        assert_eq!(stages.len(), 1);
+        assert_eq!(stages[0].name, "only");
+    }
+}
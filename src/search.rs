@@ -0,0 +1,301 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use hugr::{Hugr, HugrView, Node};
+use tket2::{circuit::CircuitHash, portmatching::CircuitPattern};
+
+use crate::commutation::slide_commuting;
+use crate::pattern::Pattern;
+use crate::rule_set::RuleSet;
+use crate::rules::Rule;
+use crate::utils::{op_name, topological_order};
+
+/// The resources used by an MBQC pattern in HUGR form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MbqcCost {
+    /// The number of `PrepPlus` nodes.
+    pub ancillas: usize,
+    /// The number of `CZ` nodes.
+    pub czs: usize,
+    /// The number of rounds of measurements needed to run the pattern.
+    pub depth: usize,
+    /// The number of `XOR` nodes.
+    pub xors: usize,
+    /// The number of `CorrectionX` and `CorrectionZ` nodes followed by a `CZ`
+    /// or a measurement, rather than left on an output wire.
+    pub stray_corrections: usize,
+}
+
+/// How much each resource counts towards the total cost of a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostWeights {
+    pub ancillas: usize,
+    pub czs: usize,
+    pub depth: usize,
+    pub xors: usize,
+    pub stray_corrections: usize,
+}
+
+impl Default for CostWeights {
+    fn default() -> Self {
+        // A stray correction costs more than the ancilla it might save, so the search does not keep such circuits
+        CostWeights { ancillas: 10, czs: 2, depth: 5, xors: 1, stray_corrections: 50 }
+    }
+}
+
+impl MbqcCost {
+    /// The resources used by `circ`.
+    ///
+    /// The measurement depth is that of the pattern read from `circ` once its
+    /// signals are shifted (see `Pattern::signal_shift`), as reported by the
+    /// command line tool. If `circ` is not a pattern yet, it is the
+    /// largest number of `MeasureX` nodes on a path through the circuit.
+    pub fn of(circ: &Hugr) -> Self {
+        let mut cost = MbqcCost::default();
+        for node in circ.children(circ.root()) {
+            match op_name(circ, node).as_str() {
+                "PrepPlus" => cost.ancillas += 1,
+                "CZ" => cost.czs += 1,
+                "XOR" => cost.xors += 1,
+                "CorrectionX" | "CorrectionZ" if !on_output_wire(circ, node) => cost.stray_corrections += 1,
+                _ => (),
+            }
+        }
+        cost.depth = match Pattern::from_hugr(circ) {
+            Ok(pattern) => pattern.signal_shift().measurement_depth(),
+            Err(_) => measurements_on_paths(circ),
+        };
+        cost
+    }
+
+    /// The weighted sum of the resources.
+    pub fn total(&self, weights: &CostWeights) -> usize {
+        self.ancillas * weights.ancillas
+            + self.czs * weights.czs
+            + self.depth * weights.depth
+            + self.xors * weights.xors
+            + self.stray_corrections * weights.stray_corrections
+    }
+}
+
+impl fmt::Display for MbqcCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ancillas, {} CZs, measurement depth {}, {} XORs, {} stray corrections",
+            self.ancillas, self.czs, self.depth, self.xors, self.stray_corrections
+        )
+    }
+}
+
+/// Whether the qubit wire of `node` only goes through single-qubit gates and
+/// corrections on its way to the outputs.
+fn on_output_wire(circ: &Hugr, node: Node) -> bool {
+    let output = circ.get_io(circ.root()).unwrap()[1];
+    let mut node = node;
+    loop {
+        let Some((next, _)) = circ.linked_inputs(node, 0).next() else {
+            return false;
+        };
+        let n_outputs = circ.get_optype(next).dataflow_signature().map_or(0, |sig| sig.output().len());
+        if next == output {
+            return true;
+        } else if n_outputs != 1 || op_name(circ, next) == "MeasureX" {
+            return false;
+        }
+        node = next;
+    }
+}
+
+/// The largest number of `MeasureX` nodes on a path through `circ`.
+fn measurements_on_paths(circ: &Hugr) -> usize {
+    let mut depths: HashMap<Node, usize> = HashMap::new();
+    for node in topological_order(circ) {
+        let before = circ
+            .node_inputs(node)
+            .flat_map(|p| circ.linked_ports(node, p))
+            .map(|(prev, _)| depths.get(&prev).copied().unwrap_or(0))
+            .max()
+            .unwrap_or(0);
+        let here = (op_name(circ, node) == "MeasureX") as usize;
+        depths.insert(node, before + here);
+    }
+    depths.into_values().max().unwrap_or(0)
+}
+
+/// How the search for a cheaper circuit is carried out.
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    /// The search stops after this long, returning the best circuit found.
+    pub timeout: Duration,
+    /// Whether the rules are also applied from RHS to LHS, which may increase
+    /// the cost for a while but lets the search escape local minima.
+    pub bidirectional: bool,
+    /// At most this many circuits are kept waiting to be explored, the most
+    /// expensive ones being dropped.
+    pub queue_size: usize,
+    pub weights: CostWeights,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            timeout: Duration::from_secs(10),
+            bidirectional: true,
+            queue_size: 100,
+            weights: CostWeights::default(),
+        }
+    }
+}
+
+/// Search for a circuit equivalent to `circ` with the lowest cost, using
+/// `rules` to move from one circuit to another, modulo `commutations` as in
+/// the passes (see `slide_commuting`).
+///
+/// Unlike the passes in `rewrites.rs`, which apply every rewrite they find,
+/// this explores the circuits obtained by applying a single rewrite at a time,
+/// always expanding the cheapest circuit not explored yet (as in tket2's
+/// Badger optimiser). Each circuit has its operations slid through the
+/// commutations before being hashed and matched, and circuits already seen
+/// are skipped, using their hash. The search stops when there is nothing left
+/// to explore or when the time budget is spent, even halfway through the
+/// expansion of a circuit, and returns the cheapest circuit found.
+pub fn optimise(circ: &Hugr, rules: Vec<Rule>, commutations: Vec<(String, String)>, config: &SearchConfig) -> Hugr {
+    let mut rule_set = search_rule_set(rules, config.bidirectional);
+    rule_set.commutations = commutations;
+    let cost = |circ: &Hugr| MbqcCost::of(circ).total(&config.weights);
+    let slide = |circ: &mut Hugr| {
+        slide_commuting(circ, &rule_set.adjacencies, &rule_set.commutations);
+    };
+    let start = Instant::now();
+
+    let mut circ = circ.clone();
+    slide(&mut circ);
+    let circ = &circ;
+
+    let mut best = (cost(circ), circ.clone());
+    let mut seen = HashSet::from([circ.circuit_hash()]);
+    // The circuits waiting to be explored, cheapest (and then oldest) first
+    let mut queue = BinaryHeap::from([Reverse((best.0, 0))]);
+    let mut circuits = HashMap::from([(0, circ.clone())]);
+    let mut next_id = 1;
+    while let Some(Reverse((_, id))) = queue.pop() {
+        if start.elapsed() >= config.timeout {
+            break;
+        }
+        let current = circuits.remove(&id).unwrap();
+        for m in rule_set.matcher.find_matches(&current) {
+            // Expanding a large circuit may take a while, so the budget is also checked for each rewrite
+            if start.elapsed() >= config.timeout {
+                break;
+            }
+            let Ok(rewrite) = m.to_rewrite(&current, rule_set.rhs[m.pattern_id().0].clone()) else {
+                continue;
+            };
+            let mut next = current.clone();
+            if rewrite.apply(&mut next).is_err() {
+                continue;
+            }
+            slide(&mut next);
+            if !seen.insert(next.circuit_hash()) {
+                continue;
+            }
+            let next_cost = cost(&next);
+            if next_cost < best.0 {
+                best = (next_cost, next.clone());
+            }
+            queue.push(Reverse((next_cost, next_id)));
+            circuits.insert(next_id, next);
+            next_id += 1;
+        }
+
+        // Drop the most expensive circuits if there are too many
+        if queue.len() > config.queue_size {
+            let mut waiting: Vec<(usize, usize)> = queue.drain().map(|Reverse(entry)| entry).collect();
+            waiting.sort();
+            for (_, id) in waiting.drain(config.queue_size..) {
+                circuits.remove(&id);
+            }
+            queue = waiting.into_iter().map(Reverse).collect();
+        }
+    }
+    best.1
+}
+
+/// The rule set used by the search, with the reverse of each rule if
+/// `bidirectional` is set. Rules whose RHS cannot be used as a pattern (e.g.
+/// because it is empty) are only applied forwards.
+fn search_rule_set(rules: Vec<Rule>, bidirectional: bool) -> RuleSet {
+    let mut all_rules = rules.clone();
+    if bidirectional {
+        for rule in rules {
+            if CircuitPattern::try_from_circuit(&rule.rhs).is_err() {
+                continue;
+            }
+            all_rules.push(Rule {
+                name: format!("{} (reversed)", rule.name),
+                lhs: rule.rhs,
+                rhs: rule.lhs,
+                priority: rule.priority,
+            });
+        }
+    }
+    RuleSet::new("search", all_rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{parse_circuit, parse_named_rules};
+    use crate::test_utils::registry;
+
+    #[test]
+    fn count_stray_corrections() {
+        let reg = registry();
+        let on_outputs = parse_circuit("q, r, c: MyBool => CZ(q, r); CorrectionX(q, c); S(q)", &reg).unwrap();
+        assert_eq!(MbqcCost::of(&on_outputs).stray_corrections, 0);
+        let stray = parse_circuit("q, r, c: MyBool => CorrectionX(q, c); S(q); CZ(q, r)", &reg).unwrap();
+        assert_eq!(MbqcCost::of(&stray).stray_corrections, 1);
+    }
+
+    #[test]
+    fn depth_of_shifted_pattern() {
+        let reg = registry();
+        // The measurements are chained through their t-domains, which signal shifting removes
+        let pattern: Pattern = "I 0\nO 3\nN 1\nN 2\nN 3\nE 0 1\nE 1 2\nE 2 3\n\
+                                M 0 0.25\nM 1 0.25 [] [0]\nM 2 0.25 [0] [1]\nX 3 [2]"
+            .parse()
+            .unwrap();
+        let circ = pattern.to_hugr(&reg).unwrap();
+        assert_eq!(MbqcCost::of(&circ).depth, 2);
+    }
+
+    #[test]
+    fn search_stops_on_timeout() {
+        let reg = registry();
+        let rules = parse_named_rules("h_h: H(q); H(q) => ", &reg).unwrap();
+        let circ = parse_circuit("q => H(q); H(q)", &reg).unwrap();
+        let config = SearchConfig { timeout: Duration::ZERO, ..SearchConfig::default() };
+        let result = optimise(&circ, rules, vec![], &config);
+        assert_eq!(result.circuit_hash(), circ.circuit_hash());
+    }
+
+    #[test]
+    fn search_slides_through_commutations() {
+        let reg = registry();
+        let rules = parse_named_rules("xcorr_xcorr: CorrectionX(q, a); CorrectionX(q, b) => c = XOR(a, b); CorrectionX(q, c)", &reg).unwrap();
+        let circ = parse_circuit(
+            "q, r, a: MyBool, b: MyBool, c: MyBool => CorrectionX(q, a); CorrectionZ(q, c); CorrectionX(q, b); CZ(q, r)",
+            &reg,
+        )
+        .unwrap();
+        let commutations = vec![("CorrectionX".to_string(), "CorrectionZ".to_string())];
+        // Merging the X corrections leaves one stray correction fewer, which is only possible after sliding
+        let config = SearchConfig { bidirectional: false, ..SearchConfig::default() };
+        let result = optimise(&circ, rules, commutations, &config);
+        let corrections = result.nodes().filter(|n| op_name(&result, *n) == "CorrectionX").count();
+        assert_eq!(corrections, 1);
+    }
+}
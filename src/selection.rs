@@ -0,0 +1,229 @@
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+use hugr::Node;
+use tket2::rewrite::CircuitRewrite;
+
+use crate::rule_set::RuleSet;
+
+/// Components of the overlap graph with at most this many rewrites are solved
+/// exactly, larger ones greedily.
+const EXACT_LIMIT: usize = 24;
+
+/// How the rewrites applied in each round are chosen among the overlapping
+/// matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// Go through the rewrites in order, skipping those that overlap with a
+    /// rewrite already applied.
+    Greedy,
+    /// Pick as many non-overlapping rewrites as possible.
+    Maximum,
+    /// Pick non-overlapping rewrites matching as many nodes as possible in
+    /// total, so that larger rewrites are preferred over smaller ones.
+    MaximumWeight,
+}
+
+/// Read a selection from its name: `greedy`, `maximum` or `maximum-weight`.
+impl FromStr for Selection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "greedy" => Ok(Selection::Greedy),
+            "maximum" => Ok(Selection::Maximum),
+            "maximum-weight" => Ok(Selection::MaximumWeight),
+            _ => Err(format!("unknown selection {s}, expected greedy, maximum or maximum-weight")),
+        }
+    }
+}
+
+/// Choose a set of non-overlapping rewrites according to `selection`, keeping
+/// the order they were given in.
+///
+/// The rewrites are considered one priority level at a time, starting from the
+/// highest: at each level, a maximum (weight) set of rewrites not overlapping
+/// with each other or with those picked at higher levels is chosen. Within a
+/// level, this is solved exactly on each group of transitively overlapping
+/// rewrites of up to `EXACT_LIMIT` rewrites, and approximately on larger ones.
+/// With `Selection::Greedy` the rewrites are returned unchanged.
+pub fn select_rewrites(
+    rewrites: Vec<(usize, CircuitRewrite)>,
+    rule_set: &RuleSet,
+    selection: Selection,
+) -> Vec<(usize, CircuitRewrite)> {
+    if selection == Selection::Greedy {
+        return rewrites;
+    }
+
+    // Two rewrites conflict if they match a common node
+    let n = rewrites.len();
+    let mut by_node: HashMap<Node, Vec<usize>> = HashMap::new();
+    for (i, (_, rw)) in rewrites.iter().enumerate() {
+        for node in rw.subcircuit().nodes() {
+            by_node.entry(*node).or_default().push(i);
+        }
+    }
+    let mut conflicts = vec![BTreeSet::new(); n];
+    for sharing in by_node.values() {
+        for &i in sharing {
+            conflicts[i].extend(sharing.iter().copied().filter(|&j| j != i));
+        }
+    }
+    let weights: Vec<usize> = rewrites
+        .iter()
+        .map(|(_, rw)| match selection {
+            Selection::MaximumWeight => rw.subcircuit().nodes().len(),
+            _ => 1,
+        })
+        .collect();
+    let priorities: Vec<i32> = rewrites.iter().map(|(rule_id, _)| rule_set.priority(*rule_id)).collect();
+
+    let mut levels = priorities.clone();
+    levels.sort_by(|a, b| b.cmp(a));
+    levels.dedup();
+
+    let mut chosen = vec![false; n];
+    let mut blocked = vec![false; n];
+    for level in levels {
+        let candidates: BTreeSet<usize> = (0..n).filter(|&i| priorities[i] == level && !blocked[i]).collect();
+        for component in components(&candidates, &conflicts) {
+            let picked = match component.len() <= EXACT_LIMIT {
+                true => exact(&component, &conflicts, &weights),
+                false => approximate(&component, &conflicts, &weights),
+            };
+            for i in picked {
+                chosen[i] = true;
+                for &j in conflicts[i].iter() {
+                    blocked[j] = true;
+                }
+            }
+        }
+    }
+
+    rewrites
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| chosen[*i])
+        .map(|(_, rw)| rw)
+        .collect()
+}
+
+/// The connected components of the conflict graph restricted to `vertices`.
+fn components(vertices: &BTreeSet<usize>, conflicts: &[BTreeSet<usize>]) -> Vec<Vec<usize>> {
+    let mut seen = BTreeSet::new();
+    let mut components = vec![];
+    for &start in vertices {
+        if !seen.insert(start) {
+            continue;
+        }
+        let mut component = vec![start];
+        let mut stack = vec![start];
+        while let Some(v) = stack.pop() {
+            for &u in conflicts[v].iter() {
+                if vertices.contains(&u) && seen.insert(u) {
+                    component.push(u);
+                    stack.push(u);
+                }
+            }
+        }
+        component.sort();
+        components.push(component);
+    }
+    components
+}
+
+/// A maximum weight independent set of the conflict graph restricted to
+/// `vertices`, found by branch and bound.
+fn exact(vertices: &[usize], conflicts: &[BTreeSet<usize>], weights: &[usize]) -> Vec<usize> {
+    let local: HashMap<usize, usize> = vertices.iter().enumerate().map(|(i, v)| (*v, i)).collect();
+    let masks: Vec<u64> = vertices
+        .iter()
+        .map(|v| conflicts[*v].iter().filter_map(|u| local.get(u)).fold(0, |mask, j| mask | 1 << j))
+        .collect();
+    let w: Vec<usize> = vertices.iter().map(|v| weights[*v]).collect();
+
+    fn search(available: u64, current: (usize, u64), masks: &[u64], w: &[usize], best: &mut (usize, u64)) {
+        if available == 0 {
+            if current.0 > best.0 {
+                *best = current;
+            }
+            return;
+        }
+        // Even taking every available vertex would not beat the best set found so far
+        let bound: usize = (0..w.len()).filter(|i| available & (1 << i) != 0).map(|i| w[i]).sum();
+        if current.0 + bound <= best.0 {
+            return;
+        }
+        let v = available.trailing_zeros() as usize;
+        // Take v, ruling out the rewrites it overlaps with
+        let taken = (current.0 + w[v], current.1 | 1 << v);
+        search(available & !masks[v] & !(1 << v), taken, masks, w, best);
+        // Leave v out, which only helps if it overlaps with an available rewrite
+        if available & masks[v] != 0 {
+            search(available & !(1 << v), current, masks, w, best);
+        }
+    }
+
+    let mut best = (0, 0);
+    search((1 << vertices.len()) - 1, (0, 0), &masks, &w, &mut best);
+    (0..vertices.len()).filter(|i| best.1 & (1 << i) != 0).map(|i| vertices[i]).collect()
+}
+
+/// A large weight independent set of the conflict graph restricted to
+/// `vertices`, picking each time the vertex with the largest ratio of weight
+/// to number of remaining neighbours (plus one).
+fn approximate(vertices: &[usize], conflicts: &[BTreeSet<usize>], weights: &[usize]) -> Vec<usize> {
+    let mut remaining: BTreeSet<usize> = vertices.iter().copied().collect();
+    let mut picked = vec![];
+    while !remaining.is_empty() {
+        let degree = |v: usize| conflicts[v].iter().filter(|u| remaining.contains(u)).count();
+        let mut best = *remaining.first().unwrap();
+        for &v in remaining.iter() {
+            if weights[v] * (degree(best) + 1) > weights[best] * (degree(v) + 1) {
+                best = v;
+            }
+        }
+        picked.push(best);
+        remaining.remove(&best);
+        for u in conflicts[best].iter() {
+            remaining.remove(u);
+        }
+    }
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{parse_circuit, parse_named_rules};
+    use crate::test_utils::registry;
+    use crate::trace::RewriteTrace;
+    use crate::utils::{apply_rule_set, RewriteConfig};
+
+    /// The number of rounds needed to remove four X gates in a row.
+    fn rounds(selection: Selection) -> usize {
+        let reg = registry();
+        // The matches of the first rule come first, so that taking it blocks both matches of the second one
+        let rules = parse_named_rules("x_x_x: X(q); X(q); X(q) => X(q)\nx_x: X(q); X(q) =>", &reg).unwrap();
+        let rule_set = RuleSet::new("xs", rules);
+        let mut circ = parse_circuit("q => X(q); X(q); X(q); X(q)", &reg).unwrap();
+        let mut trace = RewriteTrace::new(rule_set.rule_names.clone());
+        let config = RewriteConfig { selection, ..RewriteConfig::default() };
+        apply_rule_set(&rule_set, &mut circ, &config, Some(&mut trace)).unwrap();
+        trace.steps.iter().map(|step| step.round).max().unwrap_or(0)
+    }
+
+    #[test]
+    fn maximum_selection_needs_fewer_rounds() {
+        assert_eq!(rounds(Selection::Greedy), 2);
+        assert_eq!(rounds(Selection::Maximum), 1);
+        assert_eq!(rounds(Selection::MaximumWeight), 1);
+    }
+
+    #[test]
+    fn parse_selection() {
+        assert_eq!("maximum-weight".parse::<Selection>(), Ok(Selection::MaximumWeight));
+        assert!("best".parse::<Selection>().is_err());
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use hugr::{Hugr, HugrView, Node, PortIndex};
+
+use crate::rules::{Rule, Stage};
+use crate::utils::op_name;
+
+/// A quantity of a circuit that the rules of a stage are meant to decrease,
+/// which cannot decrease forever.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Measure {
+    /// The number of operations with this name.
+    Count(String),
+    /// The sum of the weights of the operations, those not listed weighing 0.
+    /// The weights should not be negative.
+    Weights(BTreeMap<String, i64>),
+    /// For each listed operation, the number of unlisted operations reachable
+    /// from it along qubit wires, compared as a multiset. It decreases as the
+    /// listed operations move toward the outputs, even when one of them is
+    /// replaced by several operations further along the wires (such as a
+    /// correction pushed through a `CZ`).
+    TowardOutputs(Vec<String>),
+}
+
+impl Measure {
+    /// How much the measure of the circuit changes when `rule` is applied, or
+    /// `None` if this cannot be known from the rule alone.
+    ///
+    /// For `TowardOutputs` only the sign is given: `Some(-1)` if the measure
+    /// decreases wherever the rule is applied, `Some(0)` if it does not
+    /// increase, and `None` otherwise (see `toward_outputs`).
+    pub fn delta(&self, rule: &Rule) -> Option<i64> {
+        match self {
+            Measure::Count(name) => {
+                let count = |circ: &Hugr| op_counts(circ).get(name).copied().unwrap_or(0);
+                Some(count(&rule.rhs) - count(&rule.lhs))
+            }
+            Measure::Weights(weights) => {
+                let weight = |circ: &Hugr| -> i64 {
+                    op_counts(circ).iter().map(|(op, n)| n * weights.get(op).copied().unwrap_or(0)).sum()
+                };
+                Some(weight(&rule.rhs) - weight(&rule.lhs))
+            }
+            Measure::TowardOutputs(names) => toward_outputs(rule, names),
+        }
+    }
+}
+
+/// Whether the rules of a stage can be shown to terminate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Termination {
+    /// The weights of the operations, when some rules are decreased by none
+    /// of the measures of the stage but by the sum of these weights instead.
+    pub weights: Option<BTreeMap<String, i64>>,
+    /// The rules that may not decrease, by index.
+    pub flagged: Vec<usize>,
+}
+
+impl Termination {
+    /// Whether every rule decreases, so that the stage terminates.
+    pub fn is_proved(&self) -> bool {
+        self.flagged.is_empty()
+    }
+}
+
+/// Check that applying the rules of `stage` eventually stops, by finding a
+/// measure of the circuit that every rule decreases.
+///
+/// The measures declared by the stage (see `parse_stages`) are compared in
+/// lexicographic order: a rule is fine if the first measure it changes
+/// decreases. The rules that leave all of them unchanged must then decrease a
+/// sum of positive weights of their operations. Whether such weights exist is
+/// decided exactly, as a linear program, so a rule is only flagged if there
+/// are none decreasing it along with the rules kept before it. Rules that keep
+/// the number of each operation, such as a rule swapping two operations, can
+/// never decrease such a sum, and are flagged along with those increasing a
+/// measure. Operations slid through the commutations of the stage
+/// are not taken into account.
+pub fn check_termination(stage: &Stage) -> Termination {
+    let mut flagged = vec![];
+    let mut tied = vec![];
+    for (i, rule) in stage.rules.iter().enumerate() {
+        let first_change = stage
+            .orderings
+            .iter()
+            .map(|measure| measure.delta(rule))
+            .find(|delta| *delta != Some(0));
+        match first_change {
+            Some(Some(delta)) if delta < 0 => (),
+            Some(_) => flagged.push(i),
+            None => tied.push(i),
+        }
+    }
+    if tied.is_empty() {
+        return Termination { weights: None, flagged };
+    }
+
+    // The change in the number of each operation, for each remaining rule
+    let deltas: Vec<BTreeMap<String, i64>> = tied.iter().map(|&i| count_delta(&stage.rules[i])).collect();
+    let ops: Vec<String> = deltas.iter().flat_map(|delta| delta.keys().cloned()).collect::<BTreeSet<_>>().into_iter().collect();
+    let rows: Vec<Vec<i64>> = deltas
+        .iter()
+        .map(|delta| ops.iter().map(|op| delta.get(op).copied().unwrap_or(0)).collect())
+        .collect();
+
+    // Keep the rules that some positive weights decrease along with the ones kept before them
+    let mut kept: Vec<Vec<i64>> = vec![];
+    let mut weights = None;
+    let mut all_kept = true;
+    for (&i, row) in tied.iter().zip(rows.iter()) {
+        kept.push(row.clone());
+        match decreasing_weights(&kept) {
+            Some(found) => weights = Some(found),
+            None => {
+                kept.pop();
+                flagged.push(i);
+                all_kept = false;
+            }
+        }
+    }
+    flagged.sort();
+    let weights = weights.filter(|_| all_kept).map(|w| ops.into_iter().zip(w).collect());
+    Termination { weights, flagged }
+}
+
+/// The number of operations of each kind in a rule's LHS or RHS.
+fn op_counts(circ: &Hugr) -> BTreeMap<String, i64> {
+    let io = circ.get_io(circ.root()).unwrap();
+    let mut counts = BTreeMap::new();
+    for node in circ.children(circ.root()).filter(|n| !io.contains(n)) {
+        *counts.entry(op_name(circ, node)).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// The number of operations of each kind in the RHS of `rule`, minus the
+/// number in its LHS.
+fn count_delta(rule: &Rule) -> BTreeMap<String, i64> {
+    let mut delta = op_counts(&rule.rhs);
+    for (op, n) in op_counts(&rule.lhs) {
+        *delta.entry(op).or_insert(0) -= n;
+    }
+    delta
+}
+
+/// The sign of the change of `Measure::TowardOutputs(names)` when `rule` is
+/// applied, if it can be known from the rule alone.
+///
+/// The unlisted operations reachable from a listed operation of the circuit
+/// are those reachable inside the rule, plus those outside of it after the
+/// outputs it reaches. The measure is compared as a multiset, so it does not
+/// increase if every listed operation of the RHS can be assigned to one of the
+/// LHS which reaches (at least) the same outputs: either several of them
+/// reaching fewer unlisted operations inside the rule, or a single one reaching
+/// as many. It decreases if one of these is strictly fewer, or if an operation
+/// of the LHS is assigned none. The operations outside of the rule must reach
+/// as many unlisted operations and the same outputs through each set of qubit
+/// inputs on both sides.
+fn toward_outputs(rule: &Rule, names: &[String]) -> Option<i64> {
+    let (lhs, rhs) = (&rule.lhs, &rule.rhs);
+    let inputs = qubit_ports(lhs, lhs.get_io(lhs.root()).unwrap()[0]);
+    if inputs != qubit_ports(rhs, rhs.get_io(rhs.root()).unwrap()[0]) {
+        return None;
+    }
+    for subset in 1..(1usize << inputs.len()) {
+        let starts: Vec<usize> = (0..inputs.len()).filter(|k| subset & (1 << k) != 0).map(|k| inputs[k]).collect();
+        if reach_from_inputs(lhs, &starts, names) != reach_from_inputs(rhs, &starts, names) {
+            return None;
+        }
+    }
+
+    let listed = |circ: &Hugr| -> Vec<(usize, BTreeSet<usize>)> {
+        circ.children(circ.root())
+            .filter(|n| names.contains(&op_name(circ, *n)))
+            .map(|n| reach(circ, [n], vec![], names))
+            .collect()
+    };
+    let before = listed(lhs);
+    let mut after = listed(rhs);
+    // Assign first the operations that can go below one of the LHS
+    after.sort_by_key(|(inside, _)| *inside);
+    let mut used = vec![false; before.len()];
+    let mut shared = vec![false; before.len()];
+    let mut decreases = false;
+    for (inside, outputs) in after.iter() {
+        let below = (0..before.len()).find(|&k| !shared[k] && before[k].0 > *inside && outputs.is_subset(&before[k].1));
+        let same = (0..before.len()).find(|&k| !used[k] && before[k].0 == *inside && outputs.is_subset(&before[k].1));
+        match (below, same) {
+            (Some(k), _) => {
+                used[k] = true;
+                decreases = true;
+            }
+            (None, Some(k)) => {
+                used[k] = true;
+                shared[k] = true;
+            }
+            (None, None) => return None,
+        }
+    }
+    decreases |= used.contains(&false);
+    Some(if decreases { -1 } else { 0 })
+}
+
+/// The indices of the output ports of `node` carrying qubits (or any value
+/// that cannot be copied).
+fn qubit_ports(circ: &Hugr, node: Node) -> Vec<usize> {
+    let outputs = circ.get_optype(node).dataflow_signature().map(|sig| sig.output().clone()).unwrap_or_default();
+    outputs.iter().enumerate().filter(|(_, ty)| !ty.copyable()).map(|(p, _)| p).collect()
+}
+
+/// The number of unlisted operations reachable along qubit wires from the
+/// given qubit inputs of `circ`, and the outputs reached.
+fn reach_from_inputs(circ: &Hugr, ports: &[usize], names: &[String]) -> (usize, BTreeSet<usize>) {
+    let input = circ.get_io(circ.root()).unwrap()[0];
+    let mut starts = vec![];
+    for &p in ports {
+        for (next, np) in circ.linked_inputs(input, p) {
+            starts.push((next, np.index()));
+        }
+    }
+    reach(circ, [], starts, names)
+}
+
+/// The number of unlisted operations reachable along qubit wires from the
+/// `nodes` (excluded) and from the input ports `ports` (included), and the
+/// outputs of `circ` reached.
+fn reach(
+    circ: &Hugr,
+    nodes: impl IntoIterator<Item = Node>,
+    ports: Vec<(Node, usize)>,
+    names: &[String],
+) -> (usize, BTreeSet<usize>) {
+    let output = circ.get_io(circ.root()).unwrap()[1];
+    let mut seen: BTreeSet<Node> = BTreeSet::new();
+    let mut outputs = BTreeSet::new();
+    let mut stack: Vec<Node> = nodes.into_iter().collect();
+    for (node, p) in ports {
+        match node == output {
+            true => {
+                outputs.insert(p);
+            }
+            false if seen.insert(node) => stack.push(node),
+            false => (),
+        }
+    }
+    while let Some(node) = stack.pop() {
+        for p in qubit_ports(circ, node) {
+            for (next, np) in circ.linked_inputs(node, p) {
+                if next == output {
+                    outputs.insert(np.index());
+                } else if seen.insert(next) {
+                    stack.push(next);
+                }
+            }
+        }
+    }
+    let unlisted = seen.iter().filter(|n| !names.contains(&op_name(circ, **n))).count();
+    (unlisted, outputs)
+}
+
+/// Positive integer weights, one for each column of `deltas`, such that the
+/// weighted sum of each row is negative, if there are any.
+///
+/// Writing the weights as `1 + x` with `x >= 0`, this is the feasibility of
+/// the linear program `deltas * x <= -1 - deltas * 1`, which is decided
+/// exactly by the simplex method on rational numbers. A rational solution is
+/// then scaled up to integers.
+fn decreasing_weights(deltas: &[Vec<i64>]) -> Option<Vec<i64>> {
+    let n = deltas.first().map_or(0, |row| row.len());
+    let bounds: Vec<i64> = deltas.iter().map(|row| -1 - row.iter().sum::<i64>()).collect();
+    let x = feasible_point(deltas, &bounds, n)?;
+    let scale = x.iter().fold(1, |scale, q| lcm(scale, q.den));
+    Some(x.iter().map(|q| ((q.num + q.den) * (scale / q.den)) as i64).collect())
+}
+
+/// A point `x >= 0` with `a * x <= b`, if there is one, found with the first
+/// phase of the simplex method. Bland's rule picks the pivots, so it cannot
+/// cycle.
+fn feasible_point(a: &[Vec<i64>], b: &[i64], n: usize) -> Option<Vec<Ratio>> {
+    let m = a.len();
+    // Each row gets a slack variable, and an artificial one if its bound is negative
+    let negative: Vec<usize> = (0..m).filter(|&i| b[i] < 0).collect();
+    let cols = n + m + negative.len();
+    let mut rows: Vec<Vec<Ratio>> = vec![];
+    let mut basis = vec![];
+    for i in 0..m {
+        let sign = if b[i] < 0 { -1 } else { 1 };
+        let mut row = vec![Ratio::from(0); cols + 1];
+        for j in 0..n {
+            row[j] = Ratio::from(sign * a[i][j]);
+        }
+        row[n + i] = Ratio::from(sign);
+        row[cols] = Ratio::from(sign * b[i]);
+        match negative.iter().position(|&k| k == i) {
+            Some(k) => {
+                row[n + m + k] = Ratio::from(1);
+                basis.push(n + m + k);
+            }
+            None => basis.push(n + i),
+        }
+        rows.push(row);
+    }
+
+    // Minimise the sum of the artificial variables
+    let cost = |j: usize| Ratio::from((j >= n + m) as i64);
+    loop {
+        let reduced = |j: usize| {
+            rows.iter().zip(basis.iter()).fold(cost(j), |r, (row, &k)| r.sub(cost(k).mul(row[j])))
+        };
+        let Some(enter) = (0..cols).find(|&j| reduced(j).num < 0) else {
+            break;
+        };
+        let leave = (0..m)
+            .filter(|&i| rows[i][enter].num > 0)
+            .min_by(|&i, &k| {
+                let (ri, rk) = (rows[i][cols].div(rows[i][enter]), rows[k][cols].div(rows[k][enter]));
+                ri.cmp(&rk).then(basis[i].cmp(&basis[k]))
+            })?;
+        let pivot = rows[leave][enter];
+        for j in 0..=cols {
+            rows[leave][j] = rows[leave][j].div(pivot);
+        }
+        for i in (0..m).filter(|&i| i != leave) {
+            let factor = rows[i][enter];
+            if factor.num != 0 {
+                for j in 0..=cols {
+                    rows[i][j] = rows[i][j].sub(factor.mul(rows[leave][j]));
+                }
+            }
+        }
+        basis[leave] = enter;
+    }
+    if (0..m).any(|i| basis[i] >= n + m && rows[i][cols].num != 0) {
+        return None;
+    }
+    let mut x = vec![Ratio::from(0); n];
+    for (i, &k) in basis.iter().enumerate() {
+        if k < n {
+            x[k] = rows[i][cols];
+        }
+    }
+    Some(x)
+}
+
+/// A rational number, with a positive denominator and in lowest terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Ratio {
+    num: i128,
+    den: i128,
+}
+
+impl Ratio {
+    fn new(num: i128, den: i128) -> Self {
+        let g = gcd(num, den).max(1) * den.signum();
+        Ratio { num: num / g, den: den / g }
+    }
+
+    fn sub(self, other: Ratio) -> Ratio {
+        Ratio::new(self.num * other.den - other.num * self.den, self.den * other.den)
+    }
+
+    fn mul(self, other: Ratio) -> Ratio {
+        Ratio::new(self.num * other.num, self.den * other.den)
+    }
+
+    fn div(self, other: Ratio) -> Ratio {
+        Ratio::new(self.num * other.den, self.den * other.num)
+    }
+}
+
+impl From<i64> for Ratio {
+    fn from(n: i64) -> Self {
+        Ratio { num: n as i128, den: 1 }
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.num * other.den).cmp(&(other.num * self.den))
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    match b {
+        0 => a.abs(),
+        _ => gcd(b, a % b),
+    }
+}
+
+fn lcm(a: i128, b: i128) -> i128 {
+    a / gcd(a, b) * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rewrites::pass_stages;
+    use crate::rules::parse_named_rules;
+    use crate::test_utils::registry;
+
+    #[test]
+    fn shipped_passes_terminate() {
+        let reg = registry();
+        for (pass, stages) in pass_stages(&reg) {
+            for stage in stages.iter() {
+                let termination = check_termination(stage);
+                let flagged: Vec<&str> = termination.flagged.iter().map(|i| stage.rules[*i].name.as_str()).collect();
+                assert!(termination.is_proved(), "{pass}, stage {}: {flagged:?}", stage.name);
+            }
+        }
+    }
+
+    #[test]
+    fn toward_outputs_follows_qubit_wires() {
+        let reg = registry();
+        let rules = parse_named_rules(
+            "
+            s_cz: S(a); CZ(a, b) => CZ(a, b); S(a)
+            cz_s: CZ(a, b); S(a) => S(a); CZ(a, b)
+            xcorr_cz: CorrectionX(a, c); CZ(a, b) => CZ(a, b); d, e = Copy(c); CorrectionX(a, d); CorrectionZ(b, e)
+            ",
+            &reg,
+        )
+        .unwrap();
+        let s = Measure::TowardOutputs(vec!["S".to_string()]);
+        assert_eq!(s.delta(&rules[0]), Some(-1));
+        assert_eq!(s.delta(&rules[1]), None);
+        // A correction splitting up as it goes through a CZ still moves toward the outputs
+        let corrections = Measure::TowardOutputs(vec!["CorrectionX".to_string(), "CorrectionZ".to_string()]);
+        assert_eq!(corrections.delta(&rules[2]), Some(-1));
+        assert_eq!(corrections.delta(&rules[0]), Some(0));
+    }
+
+    #[test]
+    fn weights_are_found_exactly() {
+        // Only weights with 2 * b < a < 7 * b / 3 decrease both rows
+        let deltas = vec![vec![-1, 2], vec![3, -7]];
+        let weights = decreasing_weights(&deltas).unwrap();
+        for row in deltas.iter() {
+            assert!(row.iter().zip(weights.iter()).map(|(d, w)| d * w).sum::<i64>() < 0);
+        }
+        assert!(weights.iter().all(|w| *w > 0));
+        // A rule and its inverse cannot both decrease
+        assert_eq!(decreasing_weights(&[vec![1, -1], vec![-1, 1]]), None);
+    }
+}
@@ -0,0 +1,11 @@
+use std::path::Path;
+
+use hugr::extension::{declarative::load_extensions_file, ExtensionRegistry, PRELUDE_REGISTRY};
+
+/// The prelude along with the `ExtMBQC` extension, which the tests build their
+/// circuits and rules from.
+pub fn registry() -> ExtensionRegistry {
+    let mut reg = PRELUDE_REGISTRY.clone();
+    load_extensions_file(Path::new("./src/mbqc_ops.yaml"), &mut reg).unwrap();
+    reg
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use hugr::{Hugr, HugrView, IncomingPort, Node, NodeIndex, PortIndex};
+use serde::{Deserialize, Serialize};
+use tket2::portmatching::{CircuitPattern, PatternMatcher};
+
+use crate::commutation::{apply_slide, Slide};
+
+/// A single rewrite applied while applying rules exhaustively.
+///
+/// Nodes are identified by their index in the HUGR at the time the rewrite
+/// was applied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RewriteStep {
+    /// The round of matching and rewriting, starting at 1.
+    pub round: usize,
+    /// The index of the rule in the list of rules given.
+    pub rule: usize,
+    pub rule_name: String,
+    /// The nodes matched by the LHS of the rule, sorted.
+    pub matched: Vec<usize>,
+    /// The nodes inserted by the RHS of the rule, sorted.
+    pub replacement: Vec<usize>,
+}
+
+/// An operation moved along its wire past commuting operations, before the
+/// matching of a round.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlideStep {
+    /// The round whose matches are found after the move, starting at 1.
+    pub round: usize,
+    /// The operation moved.
+    pub node: usize,
+    /// The operation it is moved in front of, and at which of its inputs.
+    pub target: usize,
+    pub port: usize,
+}
+
+/// The sequence of rewrites applied by `apply_rules_with_config`, in the order
+/// they were applied.
+///
+/// The trace can be saved as JSON and replayed onto the original HUGR with the
+/// same rules to reproduce the result.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RewriteTrace {
+    /// The names of the rules, in the order they were given.
+    pub rule_names: Vec<String>,
+    pub steps: Vec<RewriteStep>,
+    /// The operations slid through commutations, in the order they were moved.
+    #[serde(default)]
+    pub slides: Vec<SlideStep>,
+}
+
+/// An error found while saving, loading or replaying a trace.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceError {
+    /// The trace file could not be read or written.
+    Io(String),
+    /// The trace is not valid JSON.
+    Json(String),
+    /// The rewrite of the given step could not be found or applied.
+    Replay { step: usize },
+    /// The move of the given slide could not be replayed.
+    Slide { slide: usize },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Io(message) => write!(f, "cannot access trace file: {message}"),
+            TraceError::Json(message) => write!(f, "invalid trace: {message}"),
+            TraceError::Replay { step } => write!(f, "cannot replay step {step} of the trace"),
+            TraceError::Slide { slide } => write!(f, "cannot replay slide {slide} of the trace"),
+        }
+    }
+}
+
+impl RewriteTrace {
+    /// An empty trace for the rules called `rule_names`.
+    pub fn new(rule_names: Vec<String>) -> Self {
+        RewriteTrace { rule_names, steps: vec![], slides: vec![] }
+    }
+
+    /// Record the application of `rule` during `round`.
+    pub fn record(&mut self, round: usize, rule: usize, matched: &[Node], replacement: &[Node]) {
+        let rule_name = match self.rule_names.get(rule) {
+            Some(name) => name.clone(),
+            None => format!("rule {rule}"),
+        };
+        self.steps.push(RewriteStep {
+            round,
+            rule,
+            rule_name,
+            matched: indices(matched),
+            replacement: indices(replacement),
+        });
+    }
+
+    /// Record the moves made by `slide_commuting` before the matching of `round`.
+    pub fn record_slides(&mut self, round: usize, slides: &[Slide]) {
+        self.slides.extend(slides.iter().map(|slide| SlideStep {
+            round,
+            node: slide.node.index(),
+            target: slide.target.index(),
+            port: slide.port.index(),
+        }));
+    }
+
+    pub fn to_json(&self) -> Result<String, TraceError> {
+        serde_json::to_string_pretty(self).map_err(|e| TraceError::Json(e.to_string()))
+    }
+
+    pub fn from_json(source: &str) -> Result<Self, TraceError> {
+        serde_json::from_str(source).map_err(|e| TraceError::Json(e.to_string()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TraceError> {
+        std::fs::write(path, self.to_json()?).map_err(|e| TraceError::Io(e.to_string()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, TraceError> {
+        let source = std::fs::read_to_string(path).map_err(|e| TraceError::Io(e.to_string()))?;
+        Self::from_json(&source)
+    }
+
+    /// Apply the rewrites of the trace onto `circ`, which should be the HUGR
+    /// the trace was recorded on, using the same `rules`.
+    ///
+    /// As when the trace was recorded, the operations slid through
+    /// commutations are moved first, then the matches are found at the start
+    /// of each round and converted to rewrites before any of them is applied.
+    /// The rewrite of each step is then picked by its rule and matched nodes.
+    pub fn replay(&self, rules: &[(Hugr, Hugr)], circ: &mut Hugr) -> Result<(), TraceError> {
+        // Create the pattern matcher
+        let mut lhs_of_rules = vec![];
+        for (lhs, _) in rules.iter() {
+            lhs_of_rules.push(
+                CircuitPattern::try_from_circuit(lhs).unwrap()
+            );
+        }
+        let matcher = PatternMatcher::from_patterns(lhs_of_rules);
+
+        let last_round = self.steps.iter().map(|step| step.round).chain(self.slides.iter().map(|slide| slide.round)).max();
+        let mut i = 0;
+        let mut j = 0;
+        for round in 1..=last_round.unwrap_or(0) {
+            // Move the operations slid before this round
+            while j < self.slides.len() && self.slides[j].round == round {
+                let slide = self.slide_at(j, circ)?;
+                apply_slide(circ, &slide);
+                j += 1;
+            }
+            if i == self.steps.len() || self.steps[i].round != round {
+                continue;
+            }
+            // Convert each match to a rewrite, indexed by its rule and matched nodes
+            let mut rewrites = HashMap::new();
+            for m in matcher.find_matches(circ) {
+                let rule_id = m.pattern_id().0;
+                // These matches were skipped when the trace was recorded too
+                let Ok(rw) = m.to_rewrite(circ, rules[rule_id].1.clone()) else {
+                    continue;
+                };
+                rewrites.insert((rule_id, indices(rw.subcircuit().nodes())), rw);
+            }
+            // Apply the rewrites of this round in the recorded order
+            while i < self.steps.len() && self.steps[i].round == round {
+                let step = &self.steps[i];
+                let rw = rewrites
+                    .remove(&(step.rule, step.matched.clone()))
+                    .ok_or(TraceError::Replay { step: i })?;
+                rw.apply(circ).map_err(|_| TraceError::Replay { step: i })?;
+                i += 1;
+            }
+        }
+        match (i, j) == (self.steps.len(), self.slides.len()) {
+            true => Ok(()),
+            false if i < self.steps.len() => Err(TraceError::Replay { step: i }),
+            false => Err(TraceError::Slide { slide: j }),
+        }
+    }
+
+    /// The move of slide `j`, with its nodes found in `circ` by index.
+    fn slide_at(&self, j: usize, circ: &Hugr) -> Result<Slide, TraceError> {
+        let step = &self.slides[j];
+        let find = |index: usize| {
+            let node = Node::from(portgraph::NodeIndex::new(index));
+            circ.valid_node(node).then_some(node).ok_or(TraceError::Slide { slide: j })
+        };
+        Ok(Slide { node: find(step.node)?, target: find(step.target)?, port: IncomingPort::from(step.port) })
+    }
+}
+
+/// The sorted indices of `nodes`.
+fn indices(nodes: &[Node]) -> Vec<usize> {
+    let mut indices: Vec<usize> = nodes.iter().map(|n| n.index()).collect();
+    indices.sort();
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use hugr::{extension::ExtensionRegistry, HugrView};
+    use tket2::circuit::CircuitHash;
+
+    use super::*;
+    use crate::rule_set::RuleSet;
+    use crate::rules::{parse_circuit, parse_stages};
+    use crate::test_utils::registry;
+    use crate::utils::{apply_stages_with_config, op_name, RewriteConfig};
+
+    /// Apply the stages of the rule file `source` to `circ`, returning the
+    /// rules as pairs and the trace of the rewrites.
+    fn apply_traced(source: &str, circ: &mut Hugr, reg: &ExtensionRegistry) -> (Vec<(Hugr, Hugr)>, RewriteTrace) {
+        let stages = parse_stages(source, reg).unwrap();
+        let rules = stages.iter().flat_map(|stage| stage.rules.iter().map(|rule| (rule.lhs.clone(), rule.rhs.clone()))).collect();
+        let names = stages.iter().flat_map(|stage| stage.rules.iter().map(|rule| rule.name.clone())).collect();
+        let stages: Vec<RuleSet> = stages.into_iter().map(RuleSet::from).collect();
+        let mut trace = RewriteTrace::new(names);
+        apply_stages_with_config(&stages, circ, &RewriteConfig::default(), Some(&mut trace)).unwrap();
+        (rules, trace)
+    }
+
+    #[test]
+    fn replay_reproduces_result() {
+        let reg = registry();
+        let source = "
+            stage cancel
+            h_h: H(q); H(q) =>
+            s_s: S(q); S(q) => Z(q)
+            stage merge
+            z_z: Z(q); Z(q) =>
+        ";
+        let original = parse_circuit("q, r => H(q); H(q); S(q); S(q); S(r); S(r); S(q); S(q); CZ(q, r); H(r); H(r)", &reg).unwrap();
+        let mut circ = original.clone();
+        let (rules, trace) = apply_traced(source, &mut circ, &reg);
+        assert!(trace.steps.iter().any(|step| step.rule_name == "z_z"));
+
+        // The trace survives a round trip through JSON
+        let trace = RewriteTrace::from_json(&trace.to_json().unwrap()).unwrap();
+        let mut replayed = original;
+        trace.replay(&rules, &mut replayed).unwrap();
+        assert_eq!(replayed.circuit_hash(), circ.circuit_hash());
+    }
+
+    #[test]
+    fn replay_slides() {
+        let reg = registry();
+        let source = "
+            commute CorrectionX CorrectionZ
+            xcorr_xcorr: CorrectionX(q, a); CorrectionX(q, b) => c = XOR(a, b); CorrectionX(q, c)
+        ";
+        let original = parse_circuit(
+            "q, a: MyBool, b: MyBool, c: MyBool => CorrectionX(q, a); CorrectionZ(q, c); CorrectionX(q, b)",
+            &reg,
+        )
+        .unwrap();
+        let mut circ = original.clone();
+        let (rules, trace) = apply_traced(source, &mut circ, &reg);
+        assert!(!trace.slides.is_empty());
+        assert_eq!(trace.steps.len(), 1);
+
+        let trace = RewriteTrace::from_json(&trace.to_json().unwrap()).unwrap();
+        let mut replayed = original;
+        trace.replay(&rules, &mut replayed).unwrap();
+        assert_eq!(replayed.circuit_hash(), circ.circuit_hash());
+    }
+
+    #[test]
+    fn record_replacement_nodes() {
+        let reg = registry();
+        let mut circ = parse_circuit("q => S(q); S(q); H(q); H(q)", &reg).unwrap();
+        let (_, trace) = apply_traced("s_s: S(q); S(q) => Z(q)\nh_h: H(q); H(q) =>", &mut circ, &reg);
+        let z = circ.nodes().find(|n| op_name(&circ, *n) == "Z").unwrap();
+        for step in trace.steps.iter() {
+            assert_eq!(step.matched.len(), 2);
+            match step.rule_name.as_str() {
+                "s_s" => assert_eq!(step.replacement, [z.index()]),
+                _ => assert!(step.replacement.is_empty()),
+            }
+        }
+        assert_eq!(trace.steps.len(), 2);
+    }
+}
@@ -1,10 +1,22 @@
-use std::collections::HashSet;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use hugr::{Hugr, HugrView};
-use tket2::{portmatching::{CircuitPattern, PatternMatcher}, rewrite::CircuitRewrite};
+use hugr::{
+    extension::{prelude::QB_T, ExtensionRegistry}, ops::{OpName, OpType}, types::EdgeKind, Hugr, HugrView, Node, Port
+};
+use tket2::{Tk2Op, circuit::CircuitHash, portmatching::{PatternMatch, PatternMatcher}, rewrite::CircuitRewrite};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
 use urlencoding;
 use webbrowser;
 
+use crate::commutation::slide_commuting;
+use crate::rule_set::RuleSet;
+use crate::selection::{select_rewrites, Selection};
+use crate::trace::RewriteTrace;
+
 // Adapted from tket2/src/utils.rs
 pub fn viz_hugr(hugr: &impl HugrView) {
     let mut base: String = "https://dreampuf.github.io/GraphvizOnline/#".into();
@@ -12,6 +24,135 @@ pub fn viz_hugr(hugr: &impl HugrView) {
     webbrowser::open(&base).unwrap();
 }
 
+/// The name of the operation at `node`, without its extension prefix.
+///
+/// For instance, this returns `"CZ"` for a `Tk2Op::CZ` node and `"CorrectionX"`
+/// for a `CorrectionX` node from the `ExtMBQC` extension.
+pub fn op_name(circ: &impl HugrView, node: Node) -> String {
+    let name = circ.get_optype(node).name();
+    name.rsplit('.').next().unwrap().to_string()
+}
+
+/// The operation called `name`, either a `Tk2Op` or an operation from the
+/// `ExtMBQC` extension in `registry`.
+pub fn op_from_name(name: &str, registry: &ExtensionRegistry) -> Option<OpType> {
+    if let Ok(op) = Tk2Op::from_str(name) {
+        return Some(op.into());
+    }
+    let extension = registry.get("ExtMBQC")?;
+    let op = extension.instantiate_extension_op(name, [], registry).ok()?;
+    Some(op.into())
+}
+
+/// Whether the port `port` of `node` carries a qubit.
+pub fn is_qubit_port(circ: &impl HugrView, node: Node, port: impl Into<Port>) -> bool {
+    matches!(circ.get_optype(node).port_kind(port), Some(EdgeKind::Value(ty)) if ty == QB_T)
+}
+
+/// The nodes at the top level of `circ` in topological order.
+///
+/// Ties are broken by node index, so the order is the same every time it is
+/// computed on the same HUGR.
+pub fn topological_order(circ: &impl HugrView) -> Vec<Node> {
+    // Count the number of incoming links of each node
+    let mut in_degree = HashMap::new();
+    let mut ready = BinaryHeap::new();
+    for node in circ.children(circ.root()) {
+        let degree: usize = circ
+            .node_inputs(node)
+            .map(|p| circ.linked_ports(node, p).count())
+            .sum();
+        if degree == 0 {
+            ready.push(Reverse(node));
+        }
+        in_degree.insert(node, degree);
+    }
+
+    // Kahn's algorithm, always picking the smallest node that is ready
+    let mut order = vec![];
+    while let Some(Reverse(node)) = ready.pop() {
+        order.push(node);
+        for p in circ.node_outputs(node) {
+            for (next, _) in circ.linked_ports(node, p) {
+                let degree = in_degree.get_mut(&next).unwrap();
+                *degree -= 1;
+                if *degree == 0 {
+                    ready.push(Reverse(next));
+                }
+            }
+        }
+    }
+    order
+}
+
+/// Limits on the exhaustive application of rewrite rules.
+#[derive(Debug, Clone)]
+pub struct RewriteConfig {
+    /// The maximum number of rounds of matching and rewriting. `None` means no limit.
+    pub max_rounds: Option<usize>,
+    /// Whether to stop when a circuit that was already seen is found again.
+    pub detect_cycles: bool,
+    /// Whether to apply the rewrites of each round in a canonical order, so
+    /// that the same input always yields the same output HUGR.
+    pub deterministic: bool,
+    /// Whether to look for new matches only around the nodes changed by the
+    /// previous round, rather than over the whole circuit (see
+    /// `find_matches_near`).
+    pub incremental: bool,
+    /// How the rewrites applied in each round are chosen among overlapping matches.
+    pub selection: Selection,
+    /// The thread pool used to find matches and convert them to rewrites, or
+    /// `None` to run everything on the calling thread (see `with_threads`).
+    pub pool: Option<Arc<ThreadPool>>,
+}
+
+impl Default for RewriteConfig {
+    fn default() -> Self {
+        RewriteConfig { max_rounds: None, detect_cycles: true, deterministic: true, incremental: false, selection: Selection::Greedy, pool: None }
+    }
+}
+
+impl RewriteConfig {
+    /// Use `threads` threads to find matches and convert them to rewrites.
+    /// With 1 thread everything runs on the calling thread, and 0 means one
+    /// thread per core. The thread pool is built once here and shared by every
+    /// clone of the config.
+    pub fn with_threads(self, threads: usize) -> Result<Self, ThreadPoolBuildError> {
+        let pool = match threads {
+            1 => None,
+            n => Some(Arc::new(ThreadPoolBuilder::new().num_threads(n).build()?)),
+        };
+        Ok(RewriteConfig { pool, ..self })
+    }
+}
+
+/// An error found while applying rewrite rules exhaustively.
+///
+/// The rules are identified by their index in the list of rules given.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RewriteError {
+    /// The rules were still being applied after the maximum number of rounds.
+    /// Lists the rules applied during the last round.
+    RoundLimit { rounds: usize, rules: Vec<usize> },
+    /// The circuit obtained after `round` was identical to the one obtained
+    /// after `previous`. Lists the rules applied in between.
+    Cycle { round: usize, previous: usize, rules: Vec<usize> },
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewriteError::RoundLimit { rounds, rules } => {
+                write!(f, "rules {rules:?} were still being applied after {rounds} rounds")
+            }
+            RewriteError::Cycle { round, previous, rules } => write!(
+                f,
+                "the circuit after round {round} is the same as after round {previous}, rules {rules:?} oscillate"
+            ),
+        }
+    }
+}
+
 /// Apply all of the rewrite rules on `circ` until no more can be applied.
 ///
 /// Each rule is specified as a tuple (LHS, RHS). It proceeds iteratively,
@@ -19,62 +160,354 @@ pub fn viz_hugr(hugr: &impl HugrView) {
 /// those with the corresponding RHS and then starting another round of
 /// pattern matching. It stops when no more matches are found.
 ///
-/// Rules are applied in arbitrary order, so the user should guarantee
-/// confluence of the rewrite, or otherwise be aware that the result may not
-/// be deterministic.
+/// The rewrites of each round are applied in a canonical order (see
+/// `sort_canonically`), so the result is deterministic, but it may still
+/// depend on that order unless the rules are confluent (see `critical_pairs`
+/// in `confluence.rs` to check this). Rules that undo each
+/// other are caught by the default `RewriteConfig`, see `apply_rules_with_config`.
+///
+/// This builds the pattern matcher for the rules on every call. To apply the
+/// same rules to many circuits, build a `RuleSet` once and use `apply_rule_set`.
 pub fn apply_rules_exhaustively(
     rules: Vec<(Hugr, Hugr)>,
     circ: &mut Hugr,
-) {
-    // Translate the LHS from HUGRs to patterns
-    let mut lhs_of_rules = vec![];
-    for (lhs, _) in rules.iter() {
-        lhs_of_rules.push(
-            CircuitPattern::try_from_circuit(&lhs).unwrap()
-        );
+) -> Result<(), RewriteError> {
+    apply_rules_with_config(rules, circ, &RewriteConfig::default(), None)
+}
+
+/// Apply all of the rewrite rules on `circ` until no more can be applied, or
+/// until one of the limits in `config` is hit. See `apply_rule_set`.
+pub fn apply_rules_with_config(
+    rules: Vec<(Hugr, Hugr)>,
+    circ: &mut Hugr,
+    config: &RewriteConfig,
+    trace: Option<&mut RewriteTrace>,
+) -> Result<(), RewriteError> {
+    apply_rule_set(&RuleSet::from_pairs("rules", rules), circ, config, trace)
+}
+
+/// Apply all of the rules of `rule_set` on `circ` until no more can be
+/// applied, or until one of the limits in `config` is hit.
+///
+/// If cycle detection is enabled, the hash of the circuit is recorded after
+/// each round, and an error is returned as soon as a hash is repeated, listing
+/// the rules that took the circuit back to a previous state.
+///
+/// If a `trace` is given, every rewrite applied is recorded in it. Matches
+/// that cannot be turned into a rewrite are skipped, and the rule set is done
+/// once a round applies no rewrite, even if some matches remain.
+///
+/// With incremental matching, the matches of a round that do not involve any
+/// of the nodes changed by the rewrites are kept for the next round, and new
+/// matches are only searched for around these nodes (see `find_matches_near`).
+/// This is only done after rounds that applied rules keeping the paths of the
+/// circuit (see `RuleSet::keeps_paths`); other rounds are followed by a full
+/// search.
+///
+/// If the rule set has commutations, operations are slid along their wires
+/// before each round so that the rules match modulo commutation (see
+/// `slide_commuting`). These moves are recorded in the trace as well, so that
+/// it can be replayed.
+///
+/// With a thread pool, the matches are found and converted to rewrites in
+/// parallel on it. The rewrites are then applied one after another, as they
+/// all mutate the same HUGR. They are always applied in the canonical order,
+/// as if `deterministic` was set, since the matches are not found in the same
+/// order as on a single thread; the result is then the same as on a single
+/// thread with `deterministic` set.
+pub fn apply_rule_set(
+    rule_set: &RuleSet,
+    circ: &mut Hugr,
+    config: &RewriteConfig,
+    trace: Option<&mut RewriteTrace>,
+) -> Result<(), RewriteError> {
+    apply_rule_set_rounds(rule_set, circ, config, trace).map(|_| ())
+}
+
+/// As `apply_rule_set`, returning the number of rounds in which rewrites were applied.
+fn apply_rule_set_rounds(
+    rule_set: &RuleSet,
+    circ: &mut Hugr,
+    config: &RewriteConfig,
+    trace: Option<&mut RewriteTrace>,
+) -> Result<usize, RewriteError> {
+    match &config.pool {
+        None => apply_rule_set_on(rule_set, circ, config, trace, false),
+        Some(pool) => pool.install(|| apply_rule_set_on(rule_set, circ, config, trace, true)),
     }
+}
 
-    // Create the pattern matcher
-    let matcher = PatternMatcher::from_patterns(lhs_of_rules);
+/// The body of `apply_rule_set`, running on the current thread pool if
+/// `parallel` is set.
+fn apply_rule_set_on(
+    rule_set: &RuleSet,
+    circ: &mut Hugr,
+    config: &RewriteConfig,
+    mut trace: Option<&mut RewriteTrace>,
+    parallel: bool,
+) -> Result<usize, RewriteError> {
+    let matcher = &rule_set.matcher;
+    // Bring together the operations that the rules expect to be adjacent
+    let slides = slide_commuting(circ, &rule_set.adjacencies, &rule_set.commutations);
+    if let Some(trace) = trace.as_deref_mut() {
+        trace.record_slides(1, &slides);
+    }
     // Find all matches in the current circuit
-    let mut matches = matcher.find_matches(circ);
+    let mut matches = match parallel {
+        true => find_rooted_matches(matcher, circ, circ.children(circ.root()).collect(), true),
+        false => matcher.find_matches(circ),
+    };
+
+    // The round after which each circuit was seen, and the rules applied in each round
+    let mut seen = HashMap::from([(circ.circuit_hash(), 0)]);
+    let mut applied: Vec<Vec<usize>> = vec![];
 
-    // Apply rewrites and look for matches repeatedly until there are no more
-    while matches.len() > 0 {
-        // Convert each match to a rewrite with its corresponding RHS
-        let mut rewrites = vec![];
-        for m in matches {
+    // Apply rewrites and look for matches repeatedly until no rewrite applies
+    while !matches.is_empty() {
+        if config.max_rounds.is_some_and(|max| applied.len() >= max) {
+            let rules = applied.last().cloned().unwrap_or_default();
+            return Err(RewriteError::RoundLimit { rounds: applied.len(), rules: sorted_unique(rules) });
+        }
+
+        // Convert each match to a rewrite with its corresponding RHS, skipping those that cannot be
+        let to_rewrite = |m: &PatternMatch| {
             // Identify which of the rules was matched in this case
             let rule_id = m.pattern_id().0;  // The .0 is needed to extract the usize from a PatternID
-            let rhs = &rules[rule_id].1;  // The .1 is used here to access the second element of the tuple
-            // Convert to rewrite and add to the list of rewrites to be applied
-            let rw = m.to_rewrite(circ, rhs.clone()).unwrap();
-            rewrites.push(rw);
+            let rhs = &rule_set.rhs[rule_id];
+            m.to_rewrite(circ, rhs.clone()).ok().map(|rw| (rule_id, rw))
         };
+        let mut rewrites: Vec<(usize, CircuitRewrite)> = match parallel {
+            true => matches.par_iter().filter_map(to_rewrite).collect(),
+            false => matches.iter().filter_map(to_rewrite).collect(),
+        };
+        // The matches found in parallel are in another order than `find_matches` gives
+        if config.deterministic || parallel {
+            sort_canonically(&mut rewrites, circ);
+        }
+        // Rewrites of higher priority go first, keeping the order otherwise
+        rewrites.sort_by_key(|(rule_id, _)| Reverse(rule_set.priority(*rule_id)));
+        let rewrites = select_rewrites(rewrites, rule_set, config.selection);
 
         // Apply all of non-overlapping rewrites
-        apply_non_overlapping(rewrites, circ);
-        // Find the next set of matches
-        matches = matcher.find_matches(circ);
+        let round = applied.len() + 1;
+        let changes = apply_non_overlapping(rewrites, circ, round, trace.as_deref_mut());
+        // None of the matches could be turned into a rewrite, so the circuit is left as it is
+        if changes.rules.is_empty() {
+            break;
+        }
+        applied.push(changes.rules.clone());
+        // Sliding operations changes the circuit away from the rewrites, so it needs a full search
+        let slides = slide_commuting(circ, &rule_set.adjacencies, &rule_set.commutations);
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.record_slides(round + 1, &slides);
+        }
+        let slid = !slides.is_empty();
+
+        // Stop if we have been here before
+        if config.detect_cycles {
+            if let Some(previous) = seen.insert(circ.circuit_hash(), round) {
+                let rules = applied[previous..].concat();
+                return Err(RewriteError::Cycle { round, previous, rules: sorted_unique(rules) });
+            }
+        }
+
+        // Find the next set of matches, only around the rewrites if they keep the paths of the circuit
+        let local = changes.rules.iter().all(|rule_id| rule_set.keeps_paths[*rule_id]);
+        matches = match config.incremental {
+            true if !slid && local => find_matches_near(matcher, circ, matches, &changes, rule_set.radius, parallel),
+            _ if parallel => find_rooted_matches(matcher, circ, circ.children(circ.root()).collect(), true),
+            _ => matcher.find_matches(circ),
+        };
     };
+    Ok(applied.len())
+}
+
+/// The matches of `matcher` in `circ` after a round of rewrites, given all of
+/// the matches found before the round and the nodes changed by it, when every
+/// rewrite applied keeps the paths of the circuit (see `RuleSet::keeps_paths`).
+///
+/// The only nodes whose links changed are the nodes inserted by the rewrites
+/// and the surviving nodes that were linked to a removed node. As every node of
+/// a match is within `radius` of its root, a match involving one of them is
+/// rooted in the region within `radius` of them, and those matches are found
+/// again by rooting the matcher at each node of the region. Any other match
+/// only involves nodes whose links are unchanged. Each path of the circuit
+/// going through a replacement can be rerouted through the subcircuit it
+/// replaced and vice versa, since both link the same inputs to the same
+/// outputs, so such a match is convex now if and only if it was before the
+/// round. These are then exactly the matches from before the round that are
+/// rooted outside the region and do not involve a removed node, which are
+/// kept. The result has the same matches as `matcher.find_matches(circ)`, and
+/// only the nodes around the rewrites are visited.
+fn find_matches_near(
+    matcher: &PatternMatcher,
+    circ: &Hugr,
+    previous: Vec<PatternMatch>,
+    changes: &RoundChanges,
+    radius: usize,
+    parallel: bool,
+) -> Vec<PatternMatch> {
+    // The matched nodes are removed, so their indices may be reused by the replacements
+    let boundary = changes.boundary.iter().filter(|n| !changes.removed.contains(n));
+    let seeds = changes.inserted.iter().chain(boundary).copied().filter(|n| circ.valid_node(*n));
+
+    // Grow the region around the changed nodes, one link at a time
+    let mut region: HashSet<Node> = seeds.collect();
+    let mut frontier: Vec<Node> = region.iter().copied().collect();
+    for _ in 0..radius {
+        let mut next = vec![];
+        for node in frontier {
+            for p in circ.all_node_ports(node) {
+                for (n, _) in circ.linked_ports(node, p) {
+                    if region.insert(n) {
+                        next.push(n);
+                    }
+                }
+            }
+        }
+        frontier = next;
+    }
+
+    let mut matches: Vec<PatternMatch> = previous
+        .into_iter()
+        .filter(|m| !region.contains(&m.root()))
+        .filter(|m| m.subcircuit().nodes().iter().all(|n| !changes.removed.contains(n)))
+        .collect();
+    let mut region: Vec<Node> = region.into_iter().collect();
+    region.sort();
+    matches.extend(find_rooted_matches(matcher, circ, region, parallel));
+    matches
+}
+
+/// The matches of `matcher` rooted at each of `nodes` other than the input and
+/// output nodes, in order, found in parallel if `parallel` is set.
+fn find_rooted_matches(matcher: &PatternMatcher, circ: &Hugr, nodes: Vec<Node>, parallel: bool) -> Vec<PatternMatch> {
+    let io = circ.get_io(circ.root()).unwrap();
+    let nodes: Vec<Node> = nodes.into_iter().filter(|n| !io.contains(n)).collect();
+    match parallel {
+        true => nodes.par_iter().flat_map_iter(|n| matcher.find_rooted_matches(circ, *n)).collect(),
+        false => nodes.iter().flat_map(|n| matcher.find_rooted_matches(circ, *n)).collect(),
+    }
+}
+
+/// Apply each stage of a rewrite strategy exhaustively, one after another.
+///
+/// Within each stage, the rules with higher priority are applied first when
+/// their matches overlap. See `parse_stages` for how to write a strategy as a
+/// rule file.
+pub fn apply_stages(stages: &[RuleSet], circ: &mut Hugr) -> Result<(), RewriteError> {
+    apply_stages_with_config(stages, circ, &RewriteConfig::default(), None)
+}
+
+/// Apply each stage of a rewrite strategy exhaustively, one after another, as
+/// in `apply_rule_set`.
+///
+/// The rules are identified by their index in the concatenation of the rules
+/// of all stages, and the rounds are numbered from the first stage onwards,
+/// both in errors and in the trace.
+pub fn apply_stages_with_config(
+    stages: &[RuleSet],
+    circ: &mut Hugr,
+    config: &RewriteConfig,
+    mut trace: Option<&mut RewriteTrace>,
+) -> Result<(), RewriteError> {
+    let mut first_rule = 0;
+    let mut first_round = 0;
+    for stage in stages {
+        let mut stage_trace = trace.is_some().then(|| RewriteTrace::new(stage.rule_names.clone()));
+        let result = apply_rule_set_rounds(stage, circ, config, stage_trace.as_mut());
+
+        // Number the rules and rounds from the start of the strategy
+        if let (Some(trace), Some(stage_trace)) = (trace.as_deref_mut(), stage_trace) {
+            for mut step in stage_trace.steps {
+                step.round += first_round;
+                step.rule += first_rule;
+                trace.steps.push(step);
+            }
+            for mut slide in stage_trace.slides {
+                slide.round += first_round;
+                trace.slides.push(slide);
+            }
+        }
+        let rounds = result.map_err(|e| match e {
+            RewriteError::RoundLimit { rounds, rules } => RewriteError::RoundLimit {
+                rounds: rounds + first_round,
+                rules: rules.into_iter().map(|r| r + first_rule).collect(),
+            },
+            RewriteError::Cycle { round, previous, rules } => RewriteError::Cycle {
+                round: round + first_round,
+                previous: previous + first_round,
+                rules: rules.into_iter().map(|r| r + first_rule).collect(),
+            },
+        })?;
+        first_rule += stage.rule_names.len();
+        first_round += rounds;
+    }
+    Ok(())
+}
+
+/// Sort the rewrites by the topological position of the earliest node they
+/// match, then by rule index (earlier rules first), then by the positions of
+/// the rest of the nodes they match.
+///
+/// Unlike the order in which the matches are found, this only depends on the
+/// structure of the circuit and the order of the rules.
+fn sort_canonically(rewrites: &mut [(usize, CircuitRewrite)], circ: &Hugr) {
+    let position: HashMap<Node, usize> = topological_order(circ)
+        .into_iter()
+        .enumerate()
+        .map(|(i, n)| (n, i))
+        .collect();
+    rewrites.sort_by_cached_key(|(rule_id, rw)| {
+        let mut nodes: Vec<usize> = rw.subcircuit().nodes().iter().map(|n| position[n]).collect();
+        nodes.sort();
+        (nodes[0], *rule_id, nodes)
+    });
+}
+
+fn sorted_unique(mut ids: Vec<usize>) -> Vec<usize> {
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// What a round of `apply_non_overlapping` changed in the circuit.
+struct RoundChanges {
+    /// The index of the rule of each rewrite applied.
+    rules: Vec<usize>,
+    /// The nodes matched by the rewrites applied, which were all removed.
+    removed: HashSet<Node>,
+    /// The nodes linked to the removed nodes before they were removed.
+    boundary: HashSet<Node>,
+    /// The nodes inserted by the rewrites applied.
+    inserted: HashSet<Node>,
 }
 
 /// A rewrite strategy applying as many non-overlapping rewrites as possible.
 ///
-/// The order at which the rewrites are applied is arbitrary. If a rewrite 
+/// The rewrites are applied in the order they are given. If a rewrite
 /// overlaps with a rewrite that has already been applied, it is skipped.
+/// Each rewrite comes with the index of the rule it applies, and the indices
+/// of the rules that were applied are returned along with the nodes that were
+/// changed. If a `trace` is given, each rewrite applied is recorded in it as
+/// part of `round`.
 ///
 /// This strategy will always return exactly one circuit: the original circuit
 /// with as many rewrites applied as possible.
 ///
 /// This code was adapted from tket2::strategy::GreedyRewriteStrategy
 fn apply_non_overlapping(
-    rewrites: impl IntoIterator<Item = CircuitRewrite>,
+    rewrites: impl IntoIterator<Item = (usize, CircuitRewrite)>,
     circ: &mut Hugr,
-) {
+    round: usize,
+    mut trace: Option<&mut RewriteTrace>,
+) -> RoundChanges {
     let rewrites = rewrites.into_iter();
     let mut changed_nodes = HashSet::new();
-    for rewrite in rewrites {
+    let mut boundary = HashSet::new();
+    let mut inserted = HashSet::new();
+    let mut applied = vec![];
+    for (rule_id, rewrite) in rewrites {
         if rewrite  // Skip if it changes a node that has already been changed
             .subcircuit()
             .nodes()
@@ -85,9 +518,146 @@ fn apply_non_overlapping(
         }
         // Update the set of changed nodes
         changed_nodes.extend(rewrite.subcircuit().nodes().iter().copied());
+        let matched = rewrite.subcircuit().nodes().to_vec();
+        // The ports of the neighbours of the matched nodes will be linked to the replacement
+        let mut outside = vec![];
+        for &node in matched.iter() {
+            for p in circ.all_node_ports(node) {
+                outside.extend(circ.linked_ports(node, p).filter(|(n, _)| !matched.contains(n)));
+            }
+        }
+        boundary.extend(outside.iter().map(|(n, _)| *n));
+        // The nodes the replacement may be linked to, including those sharing a port with it
+        let outer: HashSet<Node> = outside
+            .iter()
+            .flat_map(|&(n, p)| circ.linked_ports(n, p).map(|(m, _)| m).chain([n]))
+            .filter(|n| !matched.contains(n))
+            .collect();
         // Apply the rewrite
         rewrite
             .apply(circ)
             .expect("Could not perform rewrite in exhaustive strategy");
+        applied.push(rule_id);
+        let replacement = replacement_nodes(circ, &outside, &outer);
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.record(round, rule_id, &matched, &replacement);
+        }
+        inserted.extend(replacement);
+    }
+    RoundChanges { rules: applied, removed: changed_nodes, boundary, inserted }
+}
+
+/// The nodes inserted by a rewrite, given the `outside` ports that were linked
+/// to the matched nodes and all of the nodes that were linked to these ports.
+///
+/// The replacement is linked to these ports, and is explored from there
+/// without going past any of the `outer` nodes, so that only the nodes around
+/// the rewrite are visited rather than the whole circuit.
+fn replacement_nodes(circ: &Hugr, outside: &[(Node, Port)], outer: &HashSet<Node>) -> Vec<Node> {
+    let mut stack: Vec<Node> = outside
+        .iter()
+        .flat_map(|&(n, p)| circ.linked_ports(n, p))
+        .map(|(m, _)| m)
+        .filter(|m| !outer.contains(m))
+        .collect();
+    let mut found = HashSet::new();
+    while let Some(node) = stack.pop() {
+        if !found.insert(node) {
+            continue;
+        }
+        for p in circ.all_node_ports(node) {
+            stack.extend(circ.linked_ports(node, p).map(|(m, _)| m).filter(|m| !outer.contains(m)));
+        }
+    }
+    found.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rewrites::pass_stages;
+    use crate::rules::{parse_circuit, parse_stages};
+    use crate::test_utils::registry;
+
+    /// The stages of the rule file `source`.
+    fn stages(source: &str, reg: &ExtensionRegistry) -> Vec<RuleSet> {
+        parse_stages(source, reg).unwrap().into_iter().map(RuleSet::from).collect()
+    }
+
+    #[test]
+    fn number_rounds_from_first_stage() {
+        let reg = registry();
+        let stages = stages("stage cancel\nh_h: H(q); H(q) =>\nstage swap\nx_z: X(q) => Z(q)\nz_x: Z(q) => X(q)", &reg);
+        let mut circ = parse_circuit("q => H(q); H(q); X(q)", &reg).unwrap();
+        let result = apply_stages_with_config(&stages, &mut circ, &RewriteConfig::default(), None);
+        assert_eq!(result, Err(RewriteError::Cycle { round: 3, previous: 1, rules: vec![1, 2] }));
     }
-}
\ No newline at end of file
+
+    /// A circuit with overlapping matches for the rules of most passes.
+    fn example(reg: &ExtensionRegistry) -> Hugr {
+        parse_circuit(
+            "q0, q1, q2, q3 =>
+                H(q3); CX(q0, q3); CZ(q2, q3); S(q3); H(q3); H(q0); S(q1); X(q1); S(q2); CZ(q1, q2);
+                S(q3); H(q2); S(q2); Z(q2); H(q1); S(q0); CX(q2, q1); CZ(q0, q3); S(q0); H(q0)",
+            reg,
+        )
+        .unwrap()
+    }
+
+    /// Check that applying the rules of every pass with `config` gives the
+    /// same circuits as with the default config.
+    fn same_as_default(config: &RewriteConfig) {
+        let reg = registry();
+        let mut circ = example(&reg);
+        for (pass, stages) in pass_stages(&reg) {
+            let stages: Vec<RuleSet> = stages.into_iter().map(RuleSet::from).collect();
+            let mut other = circ.clone();
+            apply_stages_with_config(&stages, &mut circ, &RewriteConfig::default(), None).unwrap();
+            apply_stages_with_config(&stages, &mut other, config, None).unwrap();
+            assert_eq!(circ.circuit_hash(), other.circuit_hash(), "{pass}");
+        }
+    }
+
+    #[test]
+    fn incremental_matches_full_search_on_passes() {
+        same_as_default(&RewriteConfig { incremental: true, ..RewriteConfig::default() });
+    }
+
+    #[test]
+    fn threads_give_same_result() {
+        let config = RewriteConfig { deterministic: false, ..RewriteConfig::default() };
+        same_as_default(&config.with_threads(2).unwrap());
+    }
+
+    #[test]
+    fn rewrites_are_reproducible() {
+        let reg = registry();
+        let run = |config: &RewriteConfig| {
+            let mut circ = example(&reg);
+            for (_, stages) in pass_stages(&reg) {
+                let stages: Vec<RuleSet> = stages.into_iter().map(RuleSet::from).collect();
+                apply_stages_with_config(&stages, &mut circ, config, None).unwrap();
+            }
+            circ.circuit_hash()
+        };
+        let config = RewriteConfig::default();
+        assert_eq!(run(&config), run(&config));
+        let single = config.clone().with_threads(1).unwrap();
+        let several = config.with_threads(4).unwrap();
+        assert_eq!(run(&single), run(&several));
+        assert_eq!(run(&several), run(&several));
+    }
+
+    #[test]
+    fn rules_keeping_paths() {
+        let reg = registry();
+        let source = "
+            h_h: H(q); H(q) =>
+            cx_to_cz: CX(a, b) => H(b); CZ(a, b); H(b)
+            copy_xor: a, b = Copy(c); d = XOR(a, b) => d = ConstFalse(); DiscardSignal(c)
+        ";
+        let rule_set = RuleSet::new("rules", parse_stages(source, &reg).unwrap().remove(0).rules);
+        // The outcome no longer reaches the output of the XOR
+        assert_eq!(rule_set.keeps_paths, [true, true, false]);
+    }
+}